@@ -2,8 +2,8 @@ use rkllm_rs::prelude::*;
 
 fn main() {
     // Since we cannot link against the real library, we just demonstrate the syntax.
-    // In a real app, you would init the handle properly.
-    let _handle = unsafe { std::mem::zeroed::<LLMHandle>() };
+    // In a real app, you would init the handle properly:
+    // let handle = init_with_model_path("/path/to/model.rkllm").expect("init failed");
 
     // Mock data for cross attention
     // In reality, these would be large tensors from an encoder model
@@ -29,6 +29,7 @@ fn main() {
     // Uncomment to run (requires linking)
     let result = handle.with_cross_attn(&params, |h| {
         println!("Inside scope: Params are valid. Calling run()...");
+        assert!(h.is_cross_attn_active());
 
         // Call h.run(...) here
         // h.run(input, infer_params, callback).unwrap();
@@ -0,0 +1,712 @@
+use crate::prelude::{
+    ChatMessage, FinishReason, KeepHistory, LLMHandle, RKLLMInferParam, RKLLMInput, RKLLMInputRole,
+    RKLLMInputType, RKLLMPromptCacheParam, RkllmError, SamplingConfig, SavedConversation,
+    SavedExchange, CONVERSATION_FORMAT_VERSION,
+};
+use std::path::Path;
+
+pub trait TokenCounter {
+    fn count_tokens(&self, text: &str) -> usize;
+}
+
+impl<F: Fn(&str) -> usize> TokenCounter for F {
+    fn count_tokens(&self, text: &str) -> usize {
+        self(text)
+    }
+}
+
+// Used when no tokenizer is available. BPE vocabularies average well above three bytes per
+// token for English text, so this overestimates rather than overflowing the context.
+fn approximate_token_count(text: &str) -> usize {
+    text.len().div_ceil(3)
+}
+
+pub type Summarizer<'a> = Box<dyn FnMut(&[ChatMessage]) -> Result<String, RkllmError> + Send + 'a>;
+
+pub enum TruncationStrategy<'a> {
+    Error,
+    DropOldest,
+    KeepSystemPrompt,
+    Summarize(Summarizer<'a>),
+}
+
+// One request/reply pair and the KV cache range it occupies.
+#[derive(Debug, Clone, Copy)]
+struct Exchange {
+    first_turn: usize,
+    kv_start: i32,
+    kv_end: i32,
+}
+
+pub struct ChatSession<'a> {
+    handle: &'a LLMHandle,
+    system_prompt: Option<String>,
+    infer_param: RKLLMInferParam,
+    enable_thinking: bool,
+    turns: Vec<ChatMessage>,
+    exchanges: Vec<Exchange>,
+    context_budget: Option<usize>,
+    truncation: TruncationStrategy<'a>,
+    token_counter: Option<Box<dyn TokenCounter + Send + 'a>>,
+    summary: Option<String>,
+    // Set once `TruncationStrategy::DropOldest` dropped the exchange that carried the system
+    // prompt.
+    system_prompt_dropped: bool,
+}
+
+impl<'a> ChatSession<'a> {
+    pub fn new(handle: &'a LLMHandle) -> Self {
+        Self {
+            handle,
+            system_prompt: None,
+            infer_param: RKLLMInferParam::default(),
+            enable_thinking: false,
+            turns: Vec::new(),
+            exchanges: Vec::new(),
+            context_budget: None,
+            truncation: TruncationStrategy::KeepSystemPrompt,
+            token_counter: None,
+            summary: None,
+            system_prompt_dropped: false,
+        }
+    }
+
+    pub fn with_system_prompt(mut self, system_prompt: impl Into<String>) -> Self {
+        self.system_prompt = Some(system_prompt.into());
+        self
+    }
+
+    pub fn with_infer_param(mut self, infer_param: RKLLMInferParam) -> Self {
+        self.infer_param = infer_param;
+        self
+    }
+
+    pub fn with_thinking(mut self, enable_thinking: bool) -> Self {
+        self.enable_thinking = enable_thinking;
+        self
+    }
+
+    // Defaults to the handle's `max_context_len`.
+    pub fn with_context_budget(mut self, context_budget: usize) -> Self {
+        self.context_budget = Some(context_budget);
+        self
+    }
+
+    pub fn with_truncation(mut self, truncation: TruncationStrategy<'a>) -> Self {
+        self.truncation = truncation;
+        self
+    }
+
+    pub fn with_token_counter(mut self, token_counter: impl TokenCounter + Send + 'a) -> Self {
+        self.token_counter = Some(Box::new(token_counter));
+        self
+    }
+
+    // The runtime saves the prompt cache of every turn to `path`, so the conversation can be
+    // restored from it with `ChatSession::restore`.
+    pub fn with_prompt_cache(mut self, path: impl Into<String>) -> Self {
+        self.infer_param.prompt_cache_params = Some(RKLLMPromptCacheParam {
+            save_prompt_cache: true,
+            prompt_cache_path: path.into(),
+        });
+        self
+    }
+
+    pub fn handle(&self) -> &'a LLMHandle {
+        self.handle
+    }
+
+    pub fn system_prompt(&self) -> Option<&str> {
+        self.system_prompt.as_deref()
+    }
+
+    pub fn turns(&self) -> &[ChatMessage] {
+        &self.turns
+    }
+
+    pub fn summary(&self) -> Option<&str> {
+        self.summary.as_deref()
+    }
+
+    pub fn context_budget(&self) -> usize {
+        self.context_budget.unwrap_or_else(|| {
+            usize::try_from(self.handle.config().max_context_len).unwrap_or_default()
+        })
+    }
+
+    // Tokens the conversation currently occupies in the KV cache.
+    pub fn context_len(&self) -> Result<usize, RkllmError> {
+        if self.exchanges.is_empty() {
+            return Ok(0);
+        }
+        Ok(usize::try_from(self.kv_cache_size()?).unwrap_or_default())
+    }
+
+    pub fn send(&mut self, prompt: impl Into<String>) -> Result<String, RkllmError> {
+        self.exchange(ChatMessage::user(prompt))
+    }
+
+    pub fn send_tool_result(&mut self, content: impl Into<String>) -> Result<String, RkllmError> {
+        self.exchange(ChatMessage::tool(content))
+    }
+
+    pub fn reset(&mut self) -> Result<(), RkllmError> {
+        self.handle.clear_kv_cache(true, None, None)?;
+        self.turns.clear();
+        self.exchanges.clear();
+        self.summary = None;
+        self.system_prompt_dropped = false;
+        Ok(())
+    }
+
+    // The runtime only clears a KV range while a generation is paused, so the cache is cleared
+    // completely and the earlier turns are replayed through the chat renderer on the next turn.
+    pub fn undo_last_turn(&mut self) -> Result<Option<ChatMessage>, RkllmError> {
+        let Some(first_turn) = self.last_request() else {
+            return Ok(None);
+        };
+        if first_turn > 0 && self.handle.chat_renderer().is_none() {
+            return Err(RkllmError::UnsupportedInput(
+                "undoing a turn needs a chat renderer to replay the earlier turns",
+            ));
+        }
+        self.clear_cache()?;
+        Ok(self.turns.drain(first_turn..).next())
+    }
+
+    pub fn snapshot(&self) -> Result<SavedConversation, RkllmError> {
+        let lora_adapter = match &self.infer_param.lora_params {
+            Some(name) => Some(
+                self.handle
+                    .lora_adapters()
+                    .into_iter()
+                    .find(|adapter| &adapter.lora_adapter_name == name)
+                    .ok_or_else(|| {
+                        RkllmError::invalid_argument(format!(
+                            "LoRA adapter {} is not loaded on the handle",
+                            name
+                        ))
+                    })?,
+            ),
+            None => None,
+        };
+        let prompt_cache_path = self
+            .prompt_cache_path()
+            .map(str::to_owned)
+            .or_else(|| self.handle.prompt_cache());
+        Ok(SavedConversation {
+            version: CONVERSATION_FORMAT_VERSION,
+            model_path: self.handle.model_path().map(str::to_owned),
+            system_prompt: self.system_prompt.clone(),
+            summary: self.summary.clone(),
+            enable_thinking: self.enable_thinking,
+            messages: self.turns.clone(),
+            exchanges: self
+                .exchanges
+                .iter()
+                .map(|exchange| SavedExchange {
+                    first_turn: exchange.first_turn,
+                    kv_start: exchange.kv_start,
+                    kv_end: exchange.kv_end,
+                })
+                .collect(),
+            runtime_chat_template: self.handle.runtime_chat_template(),
+            chat_template: self
+                .handle
+                .chat_renderer()
+                .and_then(|renderer| renderer.chat_template().cloned()),
+            sampling: SamplingConfig::from(self.handle.config()),
+            lora_adapter,
+            prompt_cache_path,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), RkllmError> {
+        self.snapshot()?.save(path)
+    }
+
+    // Re-applies the chat template and LoRA adapter; the history is replayed through the chat
+    // renderer on the next turn. The saved prompt cache is not loaded: the runtime writes it while
+    // prefilling, so it lacks the last reply.
+    pub fn restore(handle: &'a LLMHandle, saved: SavedConversation) -> Result<Self, RkllmError> {
+        saved.check_version()?;
+        saved.check_model(handle)?;
+        if let Some(template) = &saved.runtime_chat_template {
+            handle.set_chat_template(
+                &template.system_prompt,
+                &template.prompt_prefix,
+                &template.prompt_postfix,
+            )?;
+        }
+        if let Some(template) = saved.chat_template {
+            handle.set_chat_renderer(template)?;
+        }
+        if !saved.messages.is_empty() && handle.chat_renderer().is_none() {
+            return Err(RkllmError::UnsupportedInput(
+                "restoring a conversation needs a chat renderer to replay it",
+            ));
+        }
+        let mut infer_param = RKLLMInferParam::default();
+        if let Some(adapter) = &saved.lora_adapter {
+            let loaded = handle
+                .lora_adapters()
+                .iter()
+                .any(|loaded| loaded.lora_adapter_name == adapter.lora_adapter_name);
+            if !loaded {
+                handle.load_lora(adapter)?;
+            }
+            infer_param.lora_params = Some(adapter.lora_adapter_name.clone());
+        }
+        if let Some(path) = &saved.prompt_cache_path {
+            infer_param.prompt_cache_params = Some(RKLLMPromptCacheParam {
+                save_prompt_cache: true,
+                prompt_cache_path: path.clone(),
+            });
+        }
+
+        let mut session = ChatSession::new(handle)
+            .with_infer_param(infer_param)
+            .with_thinking(saved.enable_thinking);
+        session.system_prompt = saved.system_prompt;
+        session.summary = saved.summary;
+        session.turns = saved.messages;
+        Ok(session)
+    }
+
+    pub fn load(handle: &'a LLMHandle, path: impl AsRef<Path>) -> Result<Self, RkllmError> {
+        Self::restore(handle, SavedConversation::load(path)?)
+    }
+
+    pub fn prompt_cache_path(&self) -> Option<&str> {
+        self.infer_param
+            .prompt_cache_params
+            .as_ref()
+            .filter(|params| params.save_prompt_cache)
+            .map(|params| params.prompt_cache_path.as_str())
+    }
+
+    // Brings the KV state back after another conversation used the handle by replaying the
+    // history on the next turn. The prompt cache cannot be used for this: the runtime saves it
+    // while prefilling, so it lacks the last reply.
+    pub(crate) fn reattach(&mut self) {
+        self.exchanges.clear();
+    }
+
+    fn exchange(&mut self, message: ChatMessage) -> Result<String, RkllmError> {
+        if message.role == RKLLMInputRole::Assistant {
+            return Err(RkllmError::invalid_argument(
+                "assistant turns are produced by the model",
+            ));
+        }
+
+        self.enforce_budget(&message)?;
+        if self.exchanges.is_empty() {
+            // Start from a clean context so leftovers from earlier runs on the handle do not
+            // leak into this conversation.
+            self.handle.clear_kv_cache(true, None, None)?;
+        }
+        let mut history = self.pending_history();
+        let input_type = match history.as_slice() {
+            [] => RKLLMInputType::Prompt(message.content.clone()),
+            // Without a renderer the runtime template has no system slot per conversation.
+            [preamble]
+                if preamble.role == RKLLMInputRole::System
+                    && self.handle.chat_renderer().is_none() =>
+            {
+                RKLLMInputType::Prompt(format!("{}\n\n{}", preamble.content, message.content))
+            }
+            _ => {
+                history.push(message.clone());
+                RKLLMInputType::Messages(history)
+            }
+        };
+
+        let kv_start = self.kv_cache_size()?;
+        let mut infer_param = self.infer_param.clone();
+        infer_param.keep_history = KeepHistory::KeepHistory;
+        let completion = self.handle.complete(
+            RKLLMInput {
+                input_type,
+                enable_thinking: self.enable_thinking,
+                role: message.role.clone(),
+            },
+            Some(infer_param),
+        )?;
+        if completion.finish_reason == FinishReason::Error {
+            return Err(RkllmError::Callback);
+        }
+        let kv_end = self.kv_cache_size()?;
+
+        self.exchanges.push(Exchange {
+            first_turn: self.turns.len(),
+            kv_start,
+            kv_end,
+        });
+        self.turns.push(message);
+        self.turns
+            .push(ChatMessage::assistant(completion.text.clone()));
+        Ok(completion.text)
+    }
+
+    // Messages the KV cache is missing ahead of the next one. Once the cache was cleared, or
+    // restored without a prompt cache, that is the preamble followed by the whole history (this
+    // needs a chat renderer).
+    fn pending_history(&self) -> Vec<ChatMessage> {
+        if !self.exchanges.is_empty() {
+            return Vec::new();
+        }
+        self.preamble()
+            .map(ChatMessage::system)
+            .into_iter()
+            .chain(self.turns.iter().cloned())
+            .collect()
+    }
+
+    // System prompt and summary, sent ahead of the history whenever the cache starts empty.
+    fn preamble(&self) -> Option<String> {
+        let system_prompt = self
+            .system_prompt
+            .as_deref()
+            .filter(|_| !self.system_prompt_dropped);
+        let parts: Vec<&str> = [system_prompt, self.summary.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect();
+        (!parts.is_empty()).then(|| parts.join("\n\n"))
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        match &self.token_counter {
+            Some(token_counter) => token_counter.count_tokens(text),
+            None => approximate_token_count(text),
+        }
+    }
+
+    fn enforce_budget(&mut self, message: &ChatMessage) -> Result<(), RkllmError> {
+        let budget = self.context_budget();
+        if budget == 0 {
+            return Ok(());
+        }
+        let max_new_tokens = usize::try_from(self.handle.max_new_tokens()).unwrap_or_default();
+        loop {
+            let history: usize = self
+                .pending_history()
+                .iter()
+                .map(|pending| self.count_tokens(&pending.content))
+                .sum();
+            let required = self.context_len()?
+                + history
+                + self.count_tokens(&message.content)
+                + max_new_tokens;
+            if required <= budget {
+                return Ok(());
+            }
+            if self.turns.is_empty() {
+                return Err(RkllmError::ContextBudgetExceeded { required, budget });
+            }
+            match &mut self.truncation {
+                TruncationStrategy::Error => {
+                    return Err(RkllmError::ContextBudgetExceeded { required, budget })
+                }
+                TruncationStrategy::DropOldest => {
+                    self.drop_oldest_exchange()?;
+                    self.system_prompt_dropped = true;
+                }
+                TruncationStrategy::KeepSystemPrompt => self.drop_oldest_exchange()?,
+                TruncationStrategy::Summarize(summarize) => {
+                    // An earlier summary is folded into the new one.
+                    let history: Vec<ChatMessage> = self
+                        .summary
+                        .iter()
+                        .map(|summary| ChatMessage::system(summary.clone()))
+                        .chain(self.turns.iter().cloned())
+                        .collect();
+                    let summary = summarize(&history)?;
+                    self.clear_cache()?;
+                    self.turns.clear();
+                    self.summary = Some(summary);
+                }
+            }
+        }
+    }
+
+    // The runtime only clears a KV range while a generation is paused, so the cache is cleared
+    // completely and the kept turns are replayed on the next turn. Without a chat renderer they
+    // cannot be replayed and the whole history goes.
+    fn drop_oldest_exchange(&mut self) -> Result<(), RkllmError> {
+        let end = match self.handle.chat_renderer() {
+            Some(_) => self
+                .turns
+                .iter()
+                .position(|turn| turn.role == RKLLMInputRole::Assistant)
+                .map_or(self.turns.len(), |reply| reply + 1),
+            None => self.turns.len(),
+        };
+        self.clear_cache()?;
+        self.turns.drain(..end);
+        Ok(())
+    }
+
+    // Index of the message that opened the last exchange.
+    fn last_request(&self) -> Option<usize> {
+        self.turns
+            .iter()
+            .rposition(|turn| turn.role != RKLLMInputRole::Assistant)
+    }
+
+    // With no exchanges left the next turn replays the history into the empty cache.
+    fn clear_cache(&mut self) -> Result<(), RkllmError> {
+        if !self.exchanges.is_empty() {
+            self.handle.clear_kv_cache(true, None, None)?;
+            self.exchanges.clear();
+        }
+        Ok(())
+    }
+
+    fn kv_cache_size(&self) -> Result<i32, RkllmError> {
+        let n_batch = usize::from(self.handle.config().extend_param.n_batch.max(1));
+        let mut cache_sizes = vec![0; n_batch];
+        self.handle.get_kv_cache_size(&mut cache_sizes)?;
+        Ok(cache_sizes[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::{ChatTemplate, LLMConfig};
+    use std::sync::Arc;
+
+    fn detached(with_renderer: bool) -> LLMHandle {
+        let renderer: Option<Arc<dyn crate::prelude::ChatRenderer>> = with_renderer
+            .then(|| Arc::new(ChatTemplate::CHATML) as Arc<dyn crate::prelude::ChatRenderer>);
+        let config = LLMConfig {
+            max_new_tokens: 4,
+            ..LLMConfig::default()
+        };
+        LLMHandle::detached(config, renderer)
+    }
+
+    fn words(text: &str) -> usize {
+        text.split_whitespace().count()
+    }
+
+    // A session whose cache was cleared, so budget checks count the replayed history.
+    fn replaying<'a>(handle: &'a LLMHandle, truncation: TruncationStrategy<'a>) -> ChatSession<'a> {
+        let mut session = ChatSession::new(handle)
+            .with_system_prompt("be brief")
+            .with_token_counter(words)
+            .with_truncation(truncation);
+        exchanges(
+            &mut session,
+            &[("one two", "three four"), ("five six", "seven eight")],
+        );
+        session.exchanges.clear();
+        session
+    }
+
+    fn exchanges(session: &mut ChatSession<'_>, replies: &[(&str, &str)]) {
+        for (index, (request, reply)) in replies.iter().enumerate() {
+            session.exchanges.push(Exchange {
+                first_turn: session.turns.len(),
+                kv_start: index as i32 * 10,
+                kv_end: (index as i32 + 1) * 10,
+            });
+            session.turns.push(ChatMessage::user(*request));
+            session.turns.push(ChatMessage::assistant(*reply));
+        }
+    }
+
+    #[test]
+    fn undo_without_history_is_a_no_op() {
+        let handle = detached(false);
+        let mut session = ChatSession::new(&handle);
+        assert_eq!(session.undo_last_turn().unwrap(), None);
+    }
+
+    #[test]
+    fn undo_drops_the_last_exchange_and_replays_the_rest() {
+        let handle = detached(true);
+        let mut session = ChatSession::new(&handle).with_system_prompt("be brief");
+        exchanges(&mut session, &[("hi", "hello"), ("how are you", "fine")]);
+        // As left by a cleared cache.
+        session.exchanges.clear();
+        let undone = session.undo_last_turn().unwrap();
+
+        assert_eq!(undone, Some(ChatMessage::user("how are you")));
+        assert_eq!(
+            session.turns(),
+            [ChatMessage::user("hi"), ChatMessage::assistant("hello")]
+        );
+        assert!(session.exchanges.is_empty());
+        assert_eq!(
+            session.pending_history(),
+            [
+                ChatMessage::system("be brief"),
+                ChatMessage::user("hi"),
+                ChatMessage::assistant("hello"),
+            ]
+        );
+    }
+
+    #[test]
+    fn undo_starts_at_a_tool_result() {
+        let handle = detached(true);
+        let mut session = ChatSession::new(&handle);
+        exchanges(&mut session, &[("weather?", "<tool_call>")]);
+        session.turns.push(ChatMessage::tool("sunny"));
+        session.turns.push(ChatMessage::assistant("It is sunny."));
+
+        assert_eq!(session.last_request(), Some(2));
+    }
+
+    #[test]
+    fn undo_needs_a_renderer_to_replay_earlier_turns() {
+        let handle = detached(false);
+        let mut session = ChatSession::new(&handle);
+        exchanges(&mut session, &[("hi", "hello"), ("bye", "goodbye")]);
+
+        assert!(matches!(
+            session.undo_last_turn(),
+            Err(RkllmError::UnsupportedInput(_))
+        ));
+        assert_eq!(session.turns().len(), 4);
+        assert_eq!(session.exchanges.len(), 2);
+    }
+
+    #[test]
+    fn failed_cache_clear_keeps_the_history() {
+        let handle = detached(true);
+        let mut session = ChatSession::new(&handle);
+        exchanges(&mut session, &[("hi", "hello")]);
+
+        assert!(matches!(
+            session.undo_last_turn(),
+            Err(RkllmError::HandleDestroyed)
+        ));
+        assert_eq!(session.turns().len(), 2);
+        assert_eq!(session.exchanges.len(), 1);
+    }
+
+    #[test]
+    fn approximate_token_count_rounds_up() {
+        assert_eq!(approximate_token_count(""), 0);
+        assert_eq!(approximate_token_count("abc"), 1);
+        assert_eq!(approximate_token_count("abcd"), 2);
+        assert_eq!(approximate_token_count("héllo"), 2);
+    }
+
+    #[test]
+    fn budget_counts_history_message_and_reply() {
+        let handle = detached(true);
+        // "be brief" + 8 history words + 1 message word + 4 new tokens.
+        let mut session = replaying(&handle, TruncationStrategy::Error).with_context_budget(15);
+        session.enforce_budget(&ChatMessage::user("nine")).unwrap();
+        assert_eq!(session.turns().len(), 4);
+
+        let mut session = replaying(&handle, TruncationStrategy::Error).with_context_budget(14);
+        assert!(matches!(
+            session.enforce_budget(&ChatMessage::user("nine")),
+            Err(RkllmError::ContextBudgetExceeded {
+                required: 15,
+                budget: 14
+            })
+        ));
+        assert_eq!(session.turns().len(), 4);
+    }
+
+    #[test]
+    fn zero_budget_is_unlimited() {
+        let handle = detached(true);
+        let mut session = replaying(&handle, TruncationStrategy::Error).with_context_budget(0);
+        session.enforce_budget(&ChatMessage::user("nine")).unwrap();
+    }
+
+    #[test]
+    fn keep_system_prompt_drops_the_oldest_exchange() {
+        let handle = detached(true);
+        let mut session =
+            replaying(&handle, TruncationStrategy::KeepSystemPrompt).with_context_budget(11);
+        session.enforce_budget(&ChatMessage::user("nine")).unwrap();
+
+        assert_eq!(
+            session.pending_history(),
+            [
+                ChatMessage::system("be brief"),
+                ChatMessage::user("five six"),
+                ChatMessage::assistant("seven eight"),
+            ]
+        );
+    }
+
+    #[test]
+    fn drop_oldest_drops_the_system_prompt_with_it() {
+        let handle = detached(true);
+        let mut session =
+            replaying(&handle, TruncationStrategy::DropOldest).with_context_budget(11);
+        session.enforce_budget(&ChatMessage::user("nine")).unwrap();
+
+        assert_eq!(
+            session.pending_history(),
+            [
+                ChatMessage::user("five six"),
+                ChatMessage::assistant("seven eight"),
+            ]
+        );
+    }
+
+    #[test]
+    fn truncation_without_renderer_drops_the_whole_history() {
+        let handle = detached(false);
+        let mut session =
+            replaying(&handle, TruncationStrategy::KeepSystemPrompt).with_context_budget(11);
+        session.enforce_budget(&ChatMessage::user("nine")).unwrap();
+
+        assert!(session.turns().is_empty());
+        assert_eq!(session.pending_history(), [ChatMessage::system("be brief")]);
+    }
+
+    #[test]
+    fn message_over_budget_fails_once_history_is_gone() {
+        let handle = detached(true);
+        let mut session =
+            replaying(&handle, TruncationStrategy::KeepSystemPrompt).with_context_budget(6);
+        assert!(matches!(
+            session.enforce_budget(&ChatMessage::user("nine ten")),
+            Err(RkllmError::ContextBudgetExceeded {
+                required: 8,
+                budget: 6
+            })
+        ));
+        assert!(session.turns().is_empty());
+    }
+
+    #[test]
+    fn summary_is_sent_once_and_folded_into_the_next() {
+        let handle = detached(true);
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let summarized = seen.clone();
+        let summarize: Summarizer<'_> = Box::new(move |history: &[ChatMessage]| {
+            summarized.lock().unwrap().push(history.to_vec());
+            Ok("greeted".to_owned())
+        });
+        let mut session =
+            replaying(&handle, TruncationStrategy::Summarize(summarize)).with_context_budget(10);
+        session.enforce_budget(&ChatMessage::user("nine")).unwrap();
+
+        assert!(session.turns().is_empty());
+        assert_eq!(session.summary(), Some("greeted"));
+        assert_eq!(
+            session.pending_history(),
+            [ChatMessage::system("be brief\n\ngreeted")]
+        );
+
+        exchanges(&mut session, &[("ten eleven", "twelve thirteen")]);
+        session.exchanges.clear();
+        session.enforce_budget(&ChatMessage::user("nine")).unwrap();
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen[0].len(), 4);
+        assert_eq!(seen[1][0], ChatMessage::system("greeted"));
+        assert_eq!(seen[1].len(), 3);
+    }
+}
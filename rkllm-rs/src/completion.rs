@@ -0,0 +1,446 @@
+use crate::prelude::{
+    LLMHandle, RKLLMInferParam, RKLLMInput, RKLLMPerfStatData, ReasoningChunk, ReasoningParser,
+    RkllmError, Token,
+};
+#[cfg(feature = "regex")]
+use crate::prelude::{RegexConstraint, SamplingParams};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FinishReason {
+    Eos,
+    // The runtime reports both limits and EOS as a plain finish, so this is inferred from the
+    // number of generated tokens reaching `max_new_tokens`. A reply whose EOS lands exactly on
+    // the limit is reported as `MaxNewTokens` too.
+    MaxNewTokens,
+    Aborted,
+    Error,
+    Stop(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CompletionOptions {
+    pub strip_reasoning: bool,
+    pub reasoning_starts_open: bool,
+    pub stop: Vec<String>,
+    // Only honoured by `LLMHandle::complete_with`, which then generates through
+    // `complete_constrained`; it fails with `RkllmError::InvalidArgument` when `stop`, either
+    // reasoning option or the input's `enable_thinking` is set as well. See `with_regex`.
+    #[cfg(feature = "regex")]
+    pub regex: Option<RegexConstraint>,
+    // Sampling of regex completions; `None` uses the handle's `LLMConfig` with a random seed.
+    #[cfg(feature = "regex")]
+    pub sampling: Option<SamplingParams>,
+}
+
+impl CompletionOptions {
+    pub fn with_strip_reasoning(mut self, strip_reasoning: bool) -> Self {
+        self.strip_reasoning = strip_reasoning;
+        self
+    }
+
+    pub fn with_reasoning_starts_open(mut self, reasoning_starts_open: bool) -> Self {
+        self.reasoning_starts_open = reasoning_starts_open;
+        self
+    }
+
+    pub fn with_stop(mut self, stop: impl Into<String>) -> Self {
+        self.stop.push(stop.into());
+        self
+    }
+
+    // The whole output must match `regex`. Generation then samples from the runtime's logits
+    // with the handle's vocabulary (see `LLMHandle::set_vocabulary`), stopping once the match is
+    // complete and the model picks EOS or nothing else can follow. The regex applies from the
+    // first generated byte, so it cannot be combined with stop sequences, reasoning options or
+    // an input with `enable_thinking`.
+    #[cfg(feature = "regex")]
+    pub fn with_regex(mut self, regex: RegexConstraint) -> Self {
+        self.regex = Some(regex);
+        self
+    }
+
+    #[cfg(feature = "regex")]
+    pub fn with_sampling(mut self, sampling: SamplingParams) -> Self {
+        self.sampling = Some(sampling);
+        self
+    }
+
+    // Earliest stop sequence that overlaps `text[from..]`, with its byte offset. Only the new text
+    // and the tail that may complete a sequence across tokens is searched.
+    fn find_stop(&self, text: &str, from: usize) -> Option<(usize, &str)> {
+        self.stop
+            .iter()
+            .filter(|stop| !stop.is_empty())
+            .filter_map(|stop| {
+                let mut start = from.saturating_sub(stop.len() - 1);
+                while !text.is_char_boundary(start) {
+                    start -= 1;
+                }
+                text[start..]
+                    .find(stop.as_str())
+                    .map(|pos| (start + pos, stop.as_str()))
+            })
+            .min_by_key(|(pos, _)| *pos)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub text: String,
+    pub reasoning: String,
+    // Generated tokens, reasoning included. After a stop sequence only the tokens up to it are
+    // kept, including the one it starts in.
+    pub token_ids: Vec<i32>,
+    pub finish_reason: FinishReason,
+    pub perf: RKLLMPerfStatData,
+}
+
+impl LLMHandle {
+    pub fn complete(
+        &self,
+        rkllm_input: RKLLMInput,
+        rkllm_infer_params: Option<RKLLMInferParam>,
+    ) -> Result<Completion, RkllmError> {
+        self.complete_with(
+            rkllm_input,
+            rkllm_infer_params,
+            &CompletionOptions::default(),
+        )
+    }
+
+    pub fn complete_with(
+        &self,
+        rkllm_input: RKLLMInput,
+        rkllm_infer_params: Option<RKLLMInferParam>,
+        options: &CompletionOptions,
+    ) -> Result<Completion, RkllmError> {
+        #[cfg(feature = "regex")]
+        if let Some(regex) = &options.regex {
+            if !options.stop.is_empty()
+                || options.strip_reasoning
+                || options.reasoning_starts_open
+                || rkllm_input.enable_thinking
+            {
+                return Err(RkllmError::invalid_argument(
+                    "regex completions cannot be combined with stop sequences or reasoning",
+                ));
+            }
+            let vocabulary = self.vocabulary().ok_or(RkllmError::UnsupportedInput(
+                "regex completions need a vocabulary on the handle",
+            ))?;
+            return self.complete_constrained(
+                rkllm_input,
+                rkllm_infer_params,
+                vocabulary.as_ref(),
+                &mut regex.clone(),
+                &options
+                    .sampling
+                    .unwrap_or_else(|| SamplingParams::from(self.config())),
+            );
+        }
+
+        let mut generation = self.generate(rkllm_input, rkllm_infer_params)?;
+        let mut output = CompletionOutput::new(options);
+        let mut failed = false;
+
+        while let Some(token) = generation.next() {
+            match token {
+                // Tokens still in flight after a stop sequence matched are dropped.
+                Ok(_) if output.stopped.is_some() => {}
+                Ok(token) => {
+                    if output.push(&token) {
+                        generation.abort()?;
+                    }
+                }
+                // Keep the partial output; the failure is reported through `finish_reason`.
+                Err(RkllmError::Callback) => failed = true,
+                Err(err) => return Err(err),
+            }
+        }
+        output.finish();
+
+        let perf = generation.perf().unwrap_or_default();
+        let generated = usize::try_from(perf.generate_tokens)
+            .unwrap_or_default()
+            .max(output.token_ids.len());
+        let max_new_tokens = usize::try_from(self.max_new_tokens()).unwrap_or_default();
+        let CompletionOutput {
+            text,
+            reasoning,
+            answer,
+            token_ids,
+            stopped,
+            ..
+        } = output;
+        let text = if options.strip_reasoning {
+            answer.trim_start().to_owned()
+        } else {
+            text
+        };
+        let finish_reason = if let Some(stop) = stopped {
+            FinishReason::Stop(stop)
+        } else if failed {
+            FinishReason::Error
+        } else if generation.was_aborted() {
+            FinishReason::Aborted
+        } else if max_new_tokens > 0 && generated >= max_new_tokens {
+            FinishReason::MaxNewTokens
+        } else {
+            FinishReason::Eos
+        };
+
+        Ok(Completion {
+            text,
+            reasoning: reasoning.trim().to_owned(),
+            token_ids,
+            finish_reason,
+            perf,
+        })
+    }
+}
+
+// Output of a completion as it streams in. Stop sequences are only searched in the answer, so
+// the model's reasoning cannot end the completion.
+struct CompletionOutput<'o> {
+    options: &'o CompletionOptions,
+    parser: ReasoningParser,
+    text: String,
+    reasoning: String,
+    answer: String,
+    // Offsets of every answer chunk in `answer` and in `text`.
+    answer_chunks: Vec<(usize, usize)>,
+    token_ids: Vec<i32>,
+    // Offset of every token in `text`.
+    token_starts: Vec<usize>,
+    stopped: Option<String>,
+}
+
+impl<'o> CompletionOutput<'o> {
+    fn new(options: &'o CompletionOptions) -> Self {
+        Self {
+            options,
+            parser: ReasoningParser::new().starting_in_reasoning(options.reasoning_starts_open),
+            text: String::new(),
+            reasoning: String::new(),
+            answer: String::new(),
+            answer_chunks: Vec::new(),
+            token_ids: Vec::new(),
+            token_starts: Vec::new(),
+            stopped: None,
+        }
+    }
+
+    // Returns whether the token completed a stop sequence.
+    fn push(&mut self, token: &Token) -> bool {
+        self.token_starts.push(self.text.len());
+        self.text.push_str(&token.text);
+        self.token_ids.push(token.token_id);
+        let chunks = self.parser.push_at(&token.text);
+        chunks
+            .into_iter()
+            .any(|(offset, chunk)| self.take(offset, chunk))
+    }
+
+    fn finish(&mut self) {
+        if self.stopped.is_none() {
+            if let Some((offset, chunk)) = self.parser.finish_at() {
+                self.take(offset, chunk);
+            }
+        }
+    }
+
+    fn take(&mut self, offset: usize, chunk: ReasoningChunk) -> bool {
+        let chunk = match chunk {
+            ReasoningChunk::Reasoning(chunk) => {
+                self.reasoning.push_str(&chunk);
+                return false;
+            }
+            ReasoningChunk::Answer(chunk) => chunk,
+        };
+        let from = self.answer.len();
+        self.answer_chunks.push((from, offset));
+        self.answer.push_str(&chunk);
+        let Some((pos, stop)) = self.options.find_stop(&self.answer, from) else {
+            return false;
+        };
+        self.stopped = Some(stop.to_owned());
+
+        // The stop may start in an earlier chunk when it spans tokens.
+        let chunk_index = self
+            .answer_chunks
+            .partition_point(|&(answer_start, _)| answer_start <= pos)
+            - 1;
+        let (answer_start, text_start) = self.answer_chunks[chunk_index];
+        let cut = text_start + pos - answer_start;
+        self.answer.truncate(pos);
+        self.text.truncate(cut);
+        let kept = self.token_starts.partition_point(|&start| start < cut);
+        self.token_ids.truncate(kept);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(stops: &[&str]) -> CompletionOptions {
+        stops
+            .iter()
+            .fold(CompletionOptions::default(), |options, stop| {
+                options.with_stop(*stop)
+            })
+    }
+
+    #[test]
+    fn finds_a_stop_sequence_in_the_new_token() {
+        assert_eq!(
+            options(&["world"]).find_stop("Hello world", 6),
+            Some((6, "world"))
+        );
+    }
+
+    #[test]
+    fn finds_a_stop_sequence_spanning_tokens() {
+        // "Hello wor" was seen before, "ld" just arrived.
+        assert_eq!(
+            options(&["world"]).find_stop("Hello world", 9),
+            Some((6, "world"))
+        );
+    }
+
+    #[test]
+    fn does_not_search_text_before_the_tail() {
+        assert_eq!(options(&["stop"]).find_stop("stop here", 9), None);
+    }
+
+    #[test]
+    fn picks_the_earliest_stop_sequence() {
+        assert_eq!(
+            options(&["b", "ab", "x"]).find_stop("zabx", 1),
+            Some((1, "ab"))
+        );
+    }
+
+    #[test]
+    fn ignores_empty_stop_sequences() {
+        assert_eq!(options(&[""]).find_stop("text", 0), None);
+    }
+
+    #[test]
+    fn backs_off_to_a_char_boundary() {
+        // The tail of "é!" would start inside `本`.
+        assert_eq!(options(&["é!"]).find_stop("日本!", 6), None);
+        assert_eq!(
+            options(&["é!", "本!"]).find_stop("日本!", 6),
+            Some((3, "本!"))
+        );
+        assert_eq!(options(&["é"]).find_stop("aé", 1), Some((1, "é")));
+    }
+
+    // Feeds `tokens` with ids 0, 1, ... until a stop sequence matches.
+    fn stream<'o>(options: &'o CompletionOptions, tokens: &[&str]) -> CompletionOutput<'o> {
+        let mut output = CompletionOutput::new(options);
+        for (id, text) in tokens.iter().enumerate() {
+            let token = Token {
+                text: text.to_string(),
+                token_id: id as i32,
+            };
+            if output.push(&token) {
+                return output;
+            }
+        }
+        output.finish();
+        output
+    }
+
+    #[test]
+    fn stop_sequences_do_not_match_reasoning() {
+        let options = options(&["Answer:"]);
+        let output = stream(
+            &options,
+            &["<think>", "Answer: maybe", "</think>", "Answer:", " 42"],
+        );
+
+        assert_eq!(output.stopped.as_deref(), Some("Answer:"));
+        assert_eq!(output.reasoning, "Answer: maybe");
+        assert_eq!(output.answer, "");
+        assert_eq!(output.text, "<think>Answer: maybe</think>");
+        assert_eq!(output.token_ids, [0, 1, 2]);
+    }
+
+    #[test]
+    fn stop_spanning_tokens_trims_text_and_token_ids() {
+        let options = options(&["\nUser"]);
+        let output = stream(&options, &["Hi", " there\n", "Us", "er:", " more"]);
+
+        assert_eq!(output.stopped.as_deref(), Some("\nUser"));
+        assert_eq!(output.text, "Hi there");
+        assert_eq!(output.answer, "Hi there");
+        // The stop starts inside " there\n", which is kept.
+        assert_eq!(output.token_ids, [0, 1]);
+    }
+
+    #[test]
+    fn stop_at_a_token_boundary_drops_the_token() {
+        let options = options(&["END"]);
+        let output = stream(&options, &["done", "END", "!"]);
+
+        assert_eq!(output.text, "done");
+        assert_eq!(output.token_ids, [0]);
+    }
+
+    #[test]
+    fn stop_in_text_held_back_as_a_tag_prefix() {
+        // "<" could start `<think>`, so the parser only releases it when generation ends.
+        let options = options(&["<"]);
+        let output = stream(&options, &["a", "<"]);
+
+        assert_eq!(output.stopped.as_deref(), Some("<"));
+        assert_eq!(output.text, "a");
+        assert_eq!(output.token_ids, [0]);
+    }
+
+    #[test]
+    fn reasoning_that_starts_open() {
+        let options = options(&[]).with_reasoning_starts_open(true);
+        let output = stream(&options, &["hm", "</think>", "Hello"]);
+
+        assert_eq!(output.stopped, None);
+        assert_eq!(output.reasoning, "hm");
+        assert_eq!(output.answer, "Hello");
+        assert_eq!(output.text, "hm</think>Hello");
+        assert_eq!(output.token_ids, [0, 1, 2]);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn regex_completions_reject_stop_and_reasoning_options() {
+        use crate::prelude::LLMConfig;
+
+        let handle = LLMHandle::detached(LLMConfig::default(), None);
+        let regex =
+            || CompletionOptions::default().with_regex(RegexConstraint::new("yes|no").unwrap());
+        for options in [
+            regex().with_stop("."),
+            regex().with_strip_reasoning(true),
+            regex().with_reasoning_starts_open(true),
+        ] {
+            assert!(matches!(
+                handle.complete_with(RKLLMInput::prompt("?"), None, &options),
+                Err(RkllmError::InvalidArgument(_))
+            ));
+        }
+        let mut thinking = RKLLMInput::prompt("?");
+        thinking.enable_thinking = true;
+        assert!(matches!(
+            handle.complete_with(thinking, None, &regex()),
+            Err(RkllmError::InvalidArgument(_))
+        ));
+        // Without the other options, the vocabulary is checked next.
+        assert!(matches!(
+            handle.complete_with(RKLLMInput::prompt("?"), None, &regex()),
+            Err(RkllmError::UnsupportedInput(_))
+        ));
+    }
+}
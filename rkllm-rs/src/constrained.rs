@@ -0,0 +1,376 @@
+use crate::prelude::{
+    Completion, Constraint, FinishReason, KeepHistory, LLMConfig, LLMHandle, RKLLMInferMode,
+    RKLLMInferParam, RKLLMInput, RKLLMInputType, RKLLMPerfStatData, RkllmError, RkllmEvent,
+    TokenIndex, Vocabulary,
+};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Sampling done on the Rust side during constrained generation, where the runtime's sampler
+// is bypassed. Repetition penalties and mirostat are not applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplingParams {
+    // Zero or less samples greedily.
+    pub temperature: f32,
+    pub top_k: usize,
+    pub top_p: f32,
+    pub seed: Option<u64>,
+}
+
+impl Default for SamplingParams {
+    fn default() -> Self {
+        Self::greedy()
+    }
+}
+
+impl SamplingParams {
+    pub fn greedy() -> Self {
+        Self {
+            temperature: 0.0,
+            top_k: 1,
+            top_p: 1.0,
+            seed: None,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+impl From<&LLMConfig> for SamplingParams {
+    fn from(config: &LLMConfig) -> Self {
+        Self {
+            temperature: config.temperature,
+            top_k: usize::try_from(config.top_k).unwrap_or_default(),
+            top_p: config.top_p,
+            seed: None,
+        }
+    }
+}
+
+// Candidates considered when `top_k` is 0, so a step never sorts more of the allowed tokens than
+// needed.
+const DEFAULT_TOP_K: usize = 40;
+
+// SplitMix64; sampling only needs a small, seedable generator.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: Option<u64>) -> Self {
+        Rng(seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+        }))
+    }
+
+    fn next_f32(&mut self) -> f32 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+impl LLMHandle {
+    // Generates with the runtime in logits mode: every step masks the tokens `constraint`
+    // rejects, samples on the Rust side and feeds the token back as `RKLLM_INPUT_TOKEN`. EOS is
+    // only allowed once the constraint can finish, and generation stops as soon as nothing can
+    // follow. The KV cache is kept between steps and cleared at the end, also when generation
+    // fails, unless `rkllm_infer_params` asks to keep history.
+    pub fn complete_constrained(
+        &self,
+        rkllm_input: RKLLMInput,
+        rkllm_infer_params: Option<RKLLMInferParam>,
+        vocabulary: &dyn Vocabulary,
+        constraint: &mut dyn Constraint,
+        sampling: &SamplingParams,
+    ) -> Result<Completion, RkllmError> {
+        let mut infer_param = rkllm_infer_params.unwrap_or_default();
+        let keep_history = matches!(infer_param.keep_history, KeepHistory::KeepHistory);
+        infer_param.mode = RKLLMInferMode::InferGetLogits;
+        infer_param.keep_history = KeepHistory::KeepHistory;
+
+        let completion = self.constrained_steps(
+            rkllm_input,
+            &infer_param,
+            keep_history,
+            vocabulary,
+            constraint,
+            sampling,
+        );
+        if !keep_history {
+            // Every step keeps history, so the cache is cleared here on failures too.
+            let cleared = self.clear_kv_cache(true, None, None);
+            return completion.and_then(|completion| cleared.map(|_| completion));
+        }
+        completion
+    }
+
+    fn constrained_steps(
+        &self,
+        rkllm_input: RKLLMInput,
+        infer_param: &RKLLMInferParam,
+        keep_history: bool,
+        vocabulary: &dyn Vocabulary,
+        constraint: &mut dyn Constraint,
+        sampling: &SamplingParams,
+    ) -> Result<Completion, RkllmError> {
+        let enable_thinking = rkllm_input.enable_thinking;
+        let role = rkllm_input.role.clone();
+        let max_new_tokens = usize::try_from(self.max_new_tokens()).unwrap_or_default();
+        let mut rng = Rng::new(sampling.seed);
+        let index = TokenIndex::new(vocabulary);
+        let mut bytes = Vec::new();
+        let mut token_ids = Vec::new();
+        let mut perf = RKLLMPerfStatData::default();
+
+        let (mut logits, prefill) = self.next_logits(rkllm_input, infer_param)?;
+        perf.prefill_time_ms = prefill.prefill_time_ms;
+        perf.prefill_tokens = prefill.prefill_tokens;
+        perf.memory_usage_mb = prefill.memory_usage_mb;
+        let finish_reason = loop {
+            let token_id = sample(&logits, vocabulary, &index, constraint, sampling, &mut rng)?;
+            if vocabulary.is_eos(token_id) {
+                break FinishReason::Eos;
+            }
+            let token_bytes = vocabulary.token_bytes(token_id);
+            constraint.advance(token_bytes)?;
+            bytes.extend_from_slice(token_bytes);
+            token_ids.push(token_id);
+
+            let last = constraint.is_finished()
+                || (max_new_tokens > 0 && token_ids.len() >= max_new_tokens);
+            if last && !keep_history {
+                break finish_reason(constraint);
+            }
+            // With history kept, the last token has to reach the KV cache as well.
+            let (next, step) = self.next_logits(
+                RKLLMInput {
+                    input_type: RKLLMInputType::Token(vec![token_id]),
+                    enable_thinking,
+                    role: role.clone(),
+                },
+                infer_param,
+            )?;
+            perf.generate_time_ms += step.prefill_time_ms;
+            perf.memory_usage_mb = perf.memory_usage_mb.max(step.memory_usage_mb);
+            if last {
+                break finish_reason(constraint);
+            }
+            logits = next;
+        };
+        perf.generate_tokens = i32::try_from(token_ids.len()).unwrap_or(i32::MAX);
+
+        Ok(Completion {
+            text: String::from_utf8_lossy(&bytes).into_owned(),
+            reasoning: String::new(),
+            token_ids,
+            finish_reason,
+            perf,
+        })
+    }
+
+    // Runs one step in logits mode and returns the logits of the last position.
+    fn next_logits(
+        &self,
+        rkllm_input: RKLLMInput,
+        infer_param: &RKLLMInferParam,
+    ) -> Result<(Vec<f32>, RKLLMPerfStatData), RkllmError> {
+        let result = Arc::new(Mutex::new((Vec::new(), RKLLMPerfStatData::default())));
+        let sink = result.clone();
+        self.run(
+            rkllm_input,
+            Some(infer_param.clone()),
+            move |event: RkllmEvent<'_>| {
+                let Ok(mut result) = sink.lock() else {
+                    return;
+                };
+                match event {
+                    RkllmEvent::Logits(logits) => {
+                        let vocab_size = usize::try_from(logits.vocab_size).unwrap_or_default();
+                        let all = logits.logits();
+                        result.0 = all[all.len().saturating_sub(vocab_size)..].to_vec();
+                    }
+                    RkllmEvent::Finished { perf } => result.1 = perf,
+                    _ => {}
+                }
+            },
+        )?;
+        let (logits, perf) = std::mem::take(
+            &mut *result
+                .lock()
+                .map_err(|_| RkllmError::invalid_argument("logits buffer is poisoned"))?,
+        );
+        if logits.is_empty() {
+            return Err(RkllmError::UnsupportedInput(
+                "the runtime returned no logits in InferGetLogits mode",
+            ));
+        }
+        Ok((logits, perf))
+    }
+}
+
+fn finish_reason(constraint: &dyn Constraint) -> FinishReason {
+    if constraint.is_finished() {
+        FinishReason::Eos
+    } else {
+        FinishReason::MaxNewTokens
+    }
+}
+
+// Keeps the `top_k` allowed tokens with the highest logits, then samples among them with
+// temperature and top-p.
+fn sample(
+    logits: &[f32],
+    vocabulary: &dyn Vocabulary,
+    index: &TokenIndex,
+    constraint: &mut dyn Constraint,
+    sampling: &SamplingParams,
+    rng: &mut Rng,
+) -> Result<i32, RkllmError> {
+    let greedy = sampling.temperature <= 0.0 || sampling.top_k == 1;
+    let top_k = match sampling.top_k {
+        _ if greedy => 1,
+        0 => DEFAULT_TOP_K,
+        top_k => top_k,
+    };
+    let mut allowed = constraint.allowed_tokens(index);
+    if constraint.can_finish() {
+        allowed.extend_from_slice(vocabulary.eos_token_ids());
+    }
+    let mut candidates: Vec<(i32, f32)> = allowed
+        .into_iter()
+        .filter_map(|id| Some((id, *logits.get(usize::try_from(id).ok()?)?)))
+        .collect();
+    let by_logit = |a: &(i32, f32), b: &(i32, f32)| b.1.total_cmp(&a.1);
+    if candidates.len() > top_k {
+        candidates.select_nth_unstable_by(top_k - 1, by_logit);
+        candidates.truncate(top_k);
+    }
+    candidates.sort_unstable_by(by_logit);
+    let Some(&(best, best_logit)) = candidates.first() else {
+        return Err(RkllmError::NoAllowedToken);
+    };
+    if greedy {
+        return Ok(best);
+    }
+
+    let weights: Vec<f32> = candidates
+        .iter()
+        .map(|(_, logit)| ((logit - best_logit) / sampling.temperature).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    let mut kept = 0.0;
+    let mut nucleus = Vec::new();
+    for (&(id, _), weight) in candidates.iter().zip(&weights) {
+        nucleus.push((id, *weight));
+        kept += weight / total;
+        if kept >= sampling.top_p {
+            break;
+        }
+    }
+    let nucleus_total: f32 = nucleus.iter().map(|(_, weight)| weight).sum();
+    let mut target = rng.next_f32() * nucleus_total;
+    for &(id, weight) in &nucleus {
+        if target < weight {
+            return Ok(id);
+        }
+        target -= weight;
+    }
+    Ok(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::{JsonConstraint, TokenVocabulary};
+
+    fn vocabulary() -> TokenVocabulary {
+        TokenVocabulary::new(
+            ["x", "{", "}", "[", " "]
+                .iter()
+                .map(|token| token.as_bytes().to_vec())
+                .chain([Vec::new()])
+                .collect(),
+            vec![5],
+        )
+    }
+
+    #[test]
+    fn greedy_sampling_picks_the_best_allowed_token() {
+        let vocabulary = vocabulary();
+        let index = TokenIndex::new(&vocabulary);
+        let mut constraint = JsonConstraint::object();
+        let mut rng = Rng::new(Some(0));
+        let logits = [9.0, 1.0, 2.0, 8.0, 0.5, 7.0];
+        let sampled = sample(
+            &logits,
+            &vocabulary,
+            &index,
+            &mut constraint,
+            &SamplingParams::greedy(),
+            &mut rng,
+        );
+        assert_eq!(sampled.unwrap(), 1);
+
+        // EOS only once the output is complete.
+        constraint.advance(b"{}").unwrap();
+        let sampled = sample(
+            &logits,
+            &vocabulary,
+            &index,
+            &mut constraint,
+            &SamplingParams::greedy(),
+            &mut rng,
+        );
+        assert_eq!(sampled.unwrap(), 5);
+    }
+
+    #[test]
+    fn sampling_stays_within_the_top_k_allowed_tokens() {
+        let vocabulary = vocabulary();
+        let index = TokenIndex::new(&vocabulary);
+        let mut constraint = JsonConstraint::any();
+        let mut rng = Rng::new(Some(7));
+        let sampling = SamplingParams {
+            temperature: 1.0,
+            top_k: 2,
+            top_p: 1.0,
+            seed: Some(7),
+        };
+        let logits = [9.0, 3.0, 1.0, 2.5, 0.0, 0.0];
+        for _ in 0..32 {
+            let sampled = sample(
+                &logits,
+                &vocabulary,
+                &index,
+                &mut constraint,
+                &sampling,
+                &mut rng,
+            );
+            assert!(matches!(sampled, Ok(1 | 3)), "{:?}", sampled);
+        }
+    }
+
+    #[test]
+    fn fails_when_no_token_is_allowed() {
+        let vocabulary = vocabulary();
+        let index = TokenIndex::new(&vocabulary);
+        let mut constraint = JsonConstraint::any();
+        constraint.advance(b"t").unwrap();
+        let sampled = sample(
+            &[0.0; 6],
+            &vocabulary,
+            &index,
+            &mut constraint,
+            &SamplingParams::greedy(),
+            &mut Rng::new(Some(0)),
+        );
+        assert!(matches!(sampled, Err(RkllmError::NoAllowedToken)));
+    }
+}
@@ -0,0 +1,992 @@
+use crate::prelude::{RkllmError, TokenIndex};
+use serde_json::Value;
+use std::sync::Arc;
+
+// Decides which bytes may extend the output during constrained generation.
+pub trait Constraint {
+    // Whether the output accepted so far can be extended by `bytes`.
+    fn allows(&self, bytes: &[u8]) -> bool;
+
+    fn advance(&mut self, bytes: &[u8]) -> Result<(), RkllmError>;
+
+    // The output so far is complete, so generation may stop here.
+    fn can_finish(&self) -> bool;
+
+    // Nothing may follow the output so far.
+    fn is_finished(&self) -> bool;
+
+    // The tokens of `index` that `allows` accepts, in any order. The default calls `allows` for
+    // every prefix in the index, skipping the tokens below a rejected one, so it assumes every
+    // prefix of an allowed token is allowed; implementations override it to resume from the
+    // state after the prefix instead.
+    fn allowed_tokens(&mut self, index: &TokenIndex) -> Vec<i32> {
+        index.walk(Vec::new(), |prefix, byte| {
+            let mut bytes = prefix.clone();
+            bytes.push(byte);
+            self.allows(&bytes).then_some(bytes)
+        })
+    }
+}
+
+// Whitespace between tokens is free in JSON, so a long run of it is cut off to keep small
+// models from looping on indentation.
+const MAX_WHITESPACE_RUN: usize = 32;
+
+// Keywords that reference or combine schemas in ways the parser cannot follow. They fail
+// `JsonConstraint::from_schema` instead of being ignored, which would let the output break them.
+const UNSUPPORTED_KEYWORDS: [&str; 10] = [
+    "$ref",
+    "$dynamicRef",
+    "not",
+    "if",
+    "then",
+    "else",
+    "patternProperties",
+    "dependentSchemas",
+    "dependencies",
+    "prefixItems",
+];
+
+#[derive(Debug, PartialEq)]
+enum Schema {
+    Any,
+    // `None` accepts any keys with any values.
+    Object {
+        properties: Option<Vec<(String, Arc<Schema>)>>,
+        required: Vec<String>,
+    },
+    Array(Arc<Schema>),
+    String,
+    Number,
+    Integer,
+    Boolean,
+    Null,
+    // `enum`/`const` values as compact JSON.
+    Literals(Vec<Vec<u8>>),
+    Union(Vec<Arc<Schema>>),
+}
+
+impl Schema {
+    fn compile(schema: &Value) -> Result<Arc<Schema>, RkllmError> {
+        let schema = match schema {
+            Value::Bool(true) => return Ok(Arc::new(Schema::Any)),
+            Value::Object(schema) => schema,
+            _ => return Err(RkllmError::invalid_argument("unsupported JSON schema")),
+        };
+        if let Some(keyword) = UNSUPPORTED_KEYWORDS
+            .iter()
+            .find(|keyword| schema.contains_key(**keyword))
+        {
+            return Err(RkllmError::invalid_argument(format!(
+                "unsupported schema keyword {}",
+                keyword
+            )));
+        }
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            return Ok(Arc::new(Schema::Literals(
+                values
+                    .iter()
+                    .map(|value| value.to_string().into_bytes())
+                    .collect(),
+            )));
+        }
+        if let Some(value) = schema.get("const") {
+            return Ok(Arc::new(Schema::Literals(vec![value
+                .to_string()
+                .into_bytes()])));
+        }
+        for keyword in ["anyOf", "oneOf"] {
+            if let Some(branches) = schema.get(keyword).and_then(Value::as_array) {
+                return branches
+                    .iter()
+                    .map(Schema::compile)
+                    .collect::<Result<_, _>>()
+                    .map(|branches| Arc::new(Schema::Union(branches)));
+            }
+        }
+        if let Some(branches) = schema.get("allOf") {
+            return match branches.as_array().map(Vec::as_slice) {
+                Some([branch]) => Schema::compile(branch),
+                _ => Err(RkllmError::invalid_argument(
+                    "allOf is only supported with a single branch",
+                )),
+            };
+        }
+        match schema.get("type") {
+            Some(Value::String(name)) => Schema::compile_type(name, schema),
+            Some(Value::Array(names)) => names
+                .iter()
+                .map(|name| match name {
+                    Value::String(name) => Schema::compile_type(name, schema),
+                    _ => Err(RkllmError::invalid_argument("schema type must be a string")),
+                })
+                .collect::<Result<_, _>>()
+                .map(|branches| Arc::new(Schema::Union(branches))),
+            _ if schema.contains_key("properties") => Schema::compile_type("object", schema),
+            _ if schema.contains_key("items") => Schema::compile_type("array", schema),
+            _ => Ok(Arc::new(Schema::Any)),
+        }
+    }
+
+    fn compile_type(
+        name: &str,
+        schema: &serde_json::Map<String, Value>,
+    ) -> Result<Arc<Schema>, RkllmError> {
+        Ok(Arc::new(match name {
+            "object" => {
+                let properties = match schema.get("properties").and_then(Value::as_object) {
+                    Some(properties) => Some(
+                        properties
+                            .iter()
+                            .map(|(name, property)| {
+                                Schema::compile(property).map(|property| (name.clone(), property))
+                            })
+                            .collect::<Result<Vec<_>, _>>()?,
+                    ),
+                    None => None,
+                };
+                // Required names without a declared property could never be produced.
+                let required = schema
+                    .get("required")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .filter(|name| {
+                        properties.as_ref().is_some_and(|properties| {
+                            properties.iter().any(|(property, _)| property == name)
+                        })
+                    })
+                    .map(str::to_owned)
+                    .collect();
+                Schema::Object {
+                    properties,
+                    required,
+                }
+            }
+            "array" => Schema::Array(match schema.get("items") {
+                Some(items) => Schema::compile(items)?,
+                None => Arc::new(Schema::Any),
+            }),
+            "string" => Schema::String,
+            "number" => Schema::Number,
+            "integer" => Schema::Integer,
+            "boolean" => Schema::Boolean,
+            "null" => Schema::Null,
+            _ => {
+                return Err(RkllmError::invalid_argument(format!(
+                    "unsupported schema type {}",
+                    name
+                )))
+            }
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ObjectState {
+    Open,
+    ExpectKey { first: bool },
+    ExpectColon,
+    ExpectCommaOrEnd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArrayState {
+    Open,
+    Start,
+    ExpectValue,
+    ExpectCommaOrEnd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StringState {
+    Open,
+    Normal,
+    Escape,
+    Unicode(u8),
+    // Continuation bytes still expected for a multi-byte character, and the range the next one
+    // must fall in.
+    Utf8 { left: u8, low: u8, high: u8 },
+}
+
+// Continuation count and first continuation range for a UTF-8 lead byte, rejecting overlong
+// forms, surrogates and code points past U+10FFFF.
+fn utf8_lead(byte: u8) -> Option<(u8, u8, u8)> {
+    match byte {
+        0xC2..=0xDF => Some((1, 0x80, 0xBF)),
+        0xE0 => Some((2, 0xA0, 0xBF)),
+        0xED => Some((2, 0x80, 0x9F)),
+        0xE1..=0xEF => Some((2, 0x80, 0xBF)),
+        0xF0 => Some((3, 0x90, 0xBF)),
+        0xF1..=0xF3 => Some((3, 0x80, 0xBF)),
+        0xF4 => Some((3, 0x80, 0x8F)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NumberState {
+    Start,
+    Minus,
+    Zero,
+    Int,
+    Dot,
+    Fraction,
+    Exponent,
+    ExponentSign,
+    ExponentDigits,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Frame {
+    // Before a value; the first byte picks the frame that parses it.
+    Value(Arc<Schema>),
+    Object {
+        schema: Arc<Schema>,
+        seen: Vec<String>,
+        key: Option<String>,
+        state: ObjectState,
+    },
+    Array {
+        item: Arc<Schema>,
+        state: ArrayState,
+    },
+    String(StringState),
+    Number {
+        integer: bool,
+        state: NumberState,
+    },
+    Literal {
+        candidates: Vec<Vec<u8>>,
+        matched: Vec<u8>,
+    },
+}
+
+enum Step {
+    Consumed,
+    Reject,
+    // Replaces the frame and feeds the byte to the new one. Several frames fork the parse, one
+    // alternative per union branch the byte can start.
+    Replace(Vec<Frame>),
+    // The byte was consumed and starts a nested value.
+    Push(Frame),
+    // The nested frame gets the byte.
+    PushRefeed(Frame),
+    // The frame ended before the byte, which goes to the parent.
+    Done,
+    DoneConsumed,
+}
+
+impl Frame {
+    // Collects into `frames` one frame per (union) branch of `schema` that `byte` can start.
+    fn start(schema: &Arc<Schema>, byte: u8, frames: &mut Vec<Frame>) {
+        let frame = match &**schema {
+            Schema::Any => {
+                let schema = match byte {
+                    b'{' => Schema::Object {
+                        properties: None,
+                        required: Vec::new(),
+                    },
+                    b'[' => Schema::Array(Arc::new(Schema::Any)),
+                    b'"' => Schema::String,
+                    b't' | b'f' => Schema::Boolean,
+                    b'n' => Schema::Null,
+                    _ => Schema::Number,
+                };
+                return Frame::start(&Arc::new(schema), byte, frames);
+            }
+            Schema::Object { .. } if byte == b'{' => Frame::Object {
+                schema: schema.clone(),
+                seen: Vec::new(),
+                key: None,
+                state: ObjectState::Open,
+            },
+            Schema::Array(item) if byte == b'[' => Frame::Array {
+                item: item.clone(),
+                state: ArrayState::Open,
+            },
+            Schema::String if byte == b'"' => Frame::String(StringState::Open),
+            Schema::Number | Schema::Integer if byte == b'-' || byte.is_ascii_digit() => {
+                Frame::Number {
+                    integer: matches!(**schema, Schema::Integer),
+                    state: NumberState::Start,
+                }
+            }
+            Schema::Boolean if byte == b't' || byte == b'f' => {
+                Frame::literal([b"true".to_vec(), b"false".to_vec()])
+            }
+            Schema::Null if byte == b'n' => Frame::literal([b"null".to_vec()]),
+            Schema::Literals(values) if values.iter().any(|value| value.first() == Some(&byte)) => {
+                Frame::literal(values.iter().cloned())
+            }
+            Schema::Union(branches) => {
+                for branch in branches {
+                    Frame::start(branch, byte, frames);
+                }
+                return;
+            }
+            _ => return,
+        };
+        frames.push(frame);
+    }
+
+    fn literal(candidates: impl IntoIterator<Item = Vec<u8>>) -> Frame {
+        Frame::Literal {
+            candidates: candidates.into_iter().collect(),
+            matched: Vec::new(),
+        }
+    }
+
+    fn step(&mut self, byte: u8) -> Step {
+        let whitespace = matches!(byte, b' ' | b'\t' | b'\n' | b'\r');
+        match self {
+            Frame::Value(schema) => {
+                if whitespace {
+                    return Step::Consumed;
+                }
+                let mut frames = Vec::new();
+                Frame::start(schema, byte, &mut frames);
+                if frames.is_empty() {
+                    Step::Reject
+                } else {
+                    Step::Replace(frames)
+                }
+            }
+            Frame::Object {
+                schema,
+                seen,
+                key,
+                state,
+            } => {
+                let Schema::Object {
+                    properties,
+                    required,
+                } = &**schema
+                else {
+                    return Step::Reject;
+                };
+                let required_seen = || required.iter().all(|name| seen.contains(name));
+                let unseen = || {
+                    properties.as_ref().map(|properties| {
+                        properties
+                            .iter()
+                            .filter(|(name, _)| !seen.contains(name))
+                            .map(|(name, _)| Value::from(name.as_str()).to_string().into_bytes())
+                            .collect::<Vec<_>>()
+                    })
+                };
+                match *state {
+                    ObjectState::Open if byte == b'{' => {
+                        *state = ObjectState::ExpectKey { first: true };
+                        Step::Consumed
+                    }
+                    ObjectState::Open => Step::Reject,
+                    _ if whitespace => Step::Consumed,
+                    ObjectState::ExpectKey { first } => match byte {
+                        b'}' if first && required_seen() => Step::DoneConsumed,
+                        b'"' => match unseen() {
+                            Some(candidates) if candidates.is_empty() => Step::Reject,
+                            Some(candidates) => Step::PushRefeed(Frame::literal(candidates)),
+                            None => Step::PushRefeed(Frame::String(StringState::Open)),
+                        },
+                        _ => Step::Reject,
+                    },
+                    ObjectState::ExpectColon if byte == b':' => {
+                        let value = properties
+                            .iter()
+                            .flatten()
+                            .find(|(name, _)| Some(name) == key.as_ref())
+                            .map_or_else(|| Arc::new(Schema::Any), |(_, value)| value.clone());
+                        *state = ObjectState::ExpectCommaOrEnd;
+                        Step::Push(Frame::Value(value))
+                    }
+                    ObjectState::ExpectCommaOrEnd => match byte {
+                        b',' if unseen().is_none_or(|candidates| !candidates.is_empty()) => {
+                            *state = ObjectState::ExpectKey { first: false };
+                            Step::Consumed
+                        }
+                        b'}' if required_seen() => Step::DoneConsumed,
+                        _ => Step::Reject,
+                    },
+                    ObjectState::ExpectColon => Step::Reject,
+                }
+            }
+            Frame::Array { item, state } => match *state {
+                ArrayState::Open if byte == b'[' => {
+                    *state = ArrayState::Start;
+                    Step::Consumed
+                }
+                ArrayState::Open => Step::Reject,
+                _ if whitespace => Step::Consumed,
+                ArrayState::Start if byte == b']' => Step::DoneConsumed,
+                ArrayState::Start | ArrayState::ExpectValue => {
+                    *state = ArrayState::ExpectCommaOrEnd;
+                    Step::PushRefeed(Frame::Value(item.clone()))
+                }
+                ArrayState::ExpectCommaOrEnd => match byte {
+                    b',' => {
+                        *state = ArrayState::ExpectValue;
+                        Step::Consumed
+                    }
+                    b']' => Step::DoneConsumed,
+                    _ => Step::Reject,
+                },
+            },
+            Frame::String(state) => {
+                let next = match (*state, byte) {
+                    (StringState::Open, b'"') => StringState::Normal,
+                    (StringState::Open, _) => return Step::Reject,
+                    (StringState::Normal, b'"') => return Step::DoneConsumed,
+                    (StringState::Normal, b'\\') => StringState::Escape,
+                    (StringState::Normal, byte) if byte < 0x20 => return Step::Reject,
+                    (StringState::Normal, byte) if byte < 0x80 => StringState::Normal,
+                    (StringState::Normal, byte) => match utf8_lead(byte) {
+                        Some((left, low, high)) => StringState::Utf8 { left, low, high },
+                        None => return Step::Reject,
+                    },
+                    (StringState::Utf8 { left, low, high }, byte)
+                        if (low..=high).contains(&byte) =>
+                    {
+                        if left == 1 {
+                            StringState::Normal
+                        } else {
+                            StringState::Utf8 {
+                                left: left - 1,
+                                low: 0x80,
+                                high: 0xBF,
+                            }
+                        }
+                    }
+                    (StringState::Utf8 { .. }, _) => return Step::Reject,
+                    (StringState::Escape, b'u') => StringState::Unicode(4),
+                    (
+                        StringState::Escape,
+                        b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't',
+                    ) => StringState::Normal,
+                    (StringState::Escape, _) => return Step::Reject,
+                    (StringState::Unicode(left), byte) if byte.is_ascii_hexdigit() => {
+                        if left == 1 {
+                            StringState::Normal
+                        } else {
+                            StringState::Unicode(left - 1)
+                        }
+                    }
+                    (StringState::Unicode(_), _) => return Step::Reject,
+                };
+                *state = next;
+                Step::Consumed
+            }
+            Frame::Number { integer, state } => {
+                let fraction = !*integer;
+                let complete = number_complete(*state);
+                let next = match (*state, byte) {
+                    (NumberState::Start, b'-') => NumberState::Minus,
+                    (NumberState::Start | NumberState::Minus, b'0') => NumberState::Zero,
+                    (NumberState::Start | NumberState::Minus, b'1'..=b'9') => NumberState::Int,
+                    (NumberState::Int, b'0'..=b'9') => NumberState::Int,
+                    (NumberState::Zero | NumberState::Int, b'.') if fraction => NumberState::Dot,
+                    (NumberState::Dot | NumberState::Fraction, b'0'..=b'9') => {
+                        NumberState::Fraction
+                    }
+                    (NumberState::Zero | NumberState::Int | NumberState::Fraction, b'e' | b'E')
+                        if fraction =>
+                    {
+                        NumberState::Exponent
+                    }
+                    (NumberState::Exponent, b'+' | b'-') => NumberState::ExponentSign,
+                    (
+                        NumberState::Exponent
+                        | NumberState::ExponentSign
+                        | NumberState::ExponentDigits,
+                        b'0'..=b'9',
+                    ) => NumberState::ExponentDigits,
+                    _ if complete => return Step::Done,
+                    _ => return Step::Reject,
+                };
+                *state = next;
+                Step::Consumed
+            }
+            Frame::Literal {
+                candidates,
+                matched,
+            } => {
+                let len = matched.len();
+                let continues = |candidate: &Vec<u8>| {
+                    candidate.len() > len
+                        && candidate.starts_with(matched)
+                        && candidate[len] == byte
+                };
+                if candidates.iter().any(continues) {
+                    matched.push(byte);
+                    let longer = candidates.iter().any(|candidate| {
+                        candidate.len() > matched.len() && candidate.starts_with(matched)
+                    });
+                    if longer {
+                        Step::Consumed
+                    } else {
+                        Step::DoneConsumed
+                    }
+                } else if candidates.iter().any(|candidate| candidate == matched) {
+                    Step::Done
+                } else {
+                    Step::Reject
+                }
+            }
+        }
+    }
+
+    // A complete scalar that only ends where the next byte does not continue it.
+    fn is_complete(&self) -> bool {
+        match self {
+            Frame::Number { state, .. } => number_complete(*state),
+            Frame::Literal {
+                candidates,
+                matched,
+            } => candidates.iter().any(|candidate| candidate == matched),
+            _ => false,
+        }
+    }
+
+    fn child_done(&mut self, child: Frame) {
+        if let Frame::Object {
+            seen, key, state, ..
+        } = self
+        {
+            if let ObjectState::ExpectKey { .. } = state {
+                let name = match child {
+                    Frame::Literal { matched, .. } => {
+                        serde_json::from_slice(&matched).unwrap_or_default()
+                    }
+                    _ => String::new(),
+                };
+                seen.push(name.clone());
+                *key = Some(name);
+                *state = ObjectState::ExpectColon;
+            }
+        }
+    }
+
+    fn is_text(&self) -> bool {
+        matches!(self, Frame::String(_) | Frame::Literal { .. })
+    }
+}
+
+fn number_complete(state: NumberState) -> bool {
+    matches!(
+        state,
+        NumberState::Zero | NumberState::Int | NumberState::Fraction | NumberState::ExponentDigits
+    )
+}
+
+// One way of parsing the output so far.
+#[derive(Debug, Clone, PartialEq)]
+struct Parser {
+    stack: Vec<Frame>,
+    whitespace: usize,
+}
+
+impl Parser {
+    // Pushes the parsers that accept `byte` into `out`: none, this one, or one per union branch
+    // the byte can start.
+    fn feed(mut self, byte: u8, out: &mut Vec<Parser>) {
+        let whitespace = matches!(byte, b' ' | b'\t' | b'\n' | b'\r');
+        let in_text = self.stack.last().is_some_and(Frame::is_text);
+        if whitespace && !in_text {
+            self.whitespace += 1;
+            if self.whitespace > MAX_WHITESPACE_RUN {
+                return;
+            }
+        } else {
+            self.whitespace = 0;
+        }
+        self.step(byte, out);
+    }
+
+    fn step(mut self, byte: u8, out: &mut Vec<Parser>) {
+        loop {
+            let Some(top) = self.stack.last_mut() else {
+                return;
+            };
+            match top.step(byte) {
+                Step::Consumed => break,
+                Step::Reject => return,
+                Step::Replace(frames) => {
+                    for frame in frames {
+                        let mut fork = self.clone();
+                        if let Some(top) = fork.stack.last_mut() {
+                            *top = frame;
+                        }
+                        fork.step(byte, out);
+                    }
+                    return;
+                }
+                Step::Push(frame) => {
+                    self.stack.push(frame);
+                    break;
+                }
+                Step::PushRefeed(frame) => self.stack.push(frame),
+                Step::Done => self.pop(),
+                Step::DoneConsumed => {
+                    self.pop();
+                    break;
+                }
+            }
+        }
+        out.push(self);
+    }
+
+    fn pop(&mut self) {
+        if let Some(child) = self.stack.pop() {
+            if let Some(parent) = self.stack.last_mut() {
+                parent.child_done(child);
+            }
+        }
+    }
+
+    fn can_finish(&self) -> bool {
+        match self.stack.as_slice() {
+            [] => true,
+            [root] => root.is_complete(),
+            _ => false,
+        }
+    }
+}
+
+// Accepts exactly one JSON value, optionally restricted by a JSON Schema. Objects with declared
+// `properties` only accept those keys (each once) and cannot close before their `required` ones;
+// `type`, `enum`, `const`, `items`, `anyOf`/`oneOf` and single-branch `allOf` are enforced.
+// Union branches are followed side by side until the output rules them out; `oneOf` is treated
+// like `anyOf`. `$ref`, `not`, conditionals and other keywords that combine schemas are rejected,
+// while value bounds such as `minimum`, `maxLength` or `pattern` are ignored.
+#[derive(Debug, Clone)]
+pub struct JsonConstraint {
+    parsers: Vec<Parser>,
+}
+
+impl JsonConstraint {
+    pub fn any() -> Self {
+        Self::with_schema(Arc::new(Schema::Any))
+    }
+
+    // Any JSON object, the usual "JSON mode".
+    pub fn object() -> Self {
+        Self::with_schema(Arc::new(Schema::Object {
+            properties: None,
+            required: Vec::new(),
+        }))
+    }
+
+    pub fn from_schema(schema: &Value) -> Result<Self, RkllmError> {
+        Ok(Self::with_schema(Schema::compile(schema)?))
+    }
+
+    fn with_schema(schema: Arc<Schema>) -> Self {
+        Self {
+            parsers: vec![Parser {
+                stack: vec![Frame::Value(schema)],
+                whitespace: 0,
+            }],
+        }
+    }
+
+    // The constraint after `byte`, or `None` when no parse accepts it.
+    fn next(&self, byte: u8) -> Option<Self> {
+        let mut fed = Vec::new();
+        for parser in &self.parsers {
+            parser.clone().feed(byte, &mut fed);
+        }
+        // Branches that end up in the same state would otherwise multiply with every value.
+        let mut parsers = Vec::with_capacity(fed.len());
+        for parser in fed {
+            if !parsers.contains(&parser) {
+                parsers.push(parser);
+            }
+        }
+        (!parsers.is_empty()).then_some(Self { parsers })
+    }
+
+    fn after(&self, bytes: &[u8]) -> Option<Self> {
+        let (&first, rest) = bytes.split_first()?;
+        rest.iter()
+            .try_fold(self.next(first)?, |constraint, &byte| constraint.next(byte))
+    }
+}
+
+impl Constraint for JsonConstraint {
+    fn allows(&self, bytes: &[u8]) -> bool {
+        self.after(bytes).is_some()
+    }
+
+    fn advance(&mut self, bytes: &[u8]) -> Result<(), RkllmError> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        *self = self.after(bytes).ok_or_else(|| {
+            RkllmError::invalid_argument("output does not satisfy the JSON constraint")
+        })?;
+        Ok(())
+    }
+
+    fn can_finish(&self) -> bool {
+        self.parsers.iter().any(Parser::can_finish)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.parsers.iter().all(|parser| parser.stack.is_empty())
+    }
+
+    // Every trie node costs one step of the parsers alive after its prefix.
+    fn allowed_tokens(&mut self, index: &TokenIndex) -> Vec<i32> {
+        index.walk(self.clone(), |constraint, byte| constraint.next(byte))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::{TokenVocabulary, Vocabulary};
+    use serde_json::json;
+
+    // Whether `text` is a complete output for `constraint`.
+    fn accepts(constraint: &JsonConstraint, text: impl AsRef<[u8]>) -> bool {
+        let mut constraint = constraint.clone();
+        constraint.advance(text.as_ref()).is_ok() && constraint.can_finish()
+    }
+
+    fn schema(schema: Value) -> JsonConstraint {
+        JsonConstraint::from_schema(&schema).unwrap()
+    }
+
+    #[test]
+    fn accepts_any_json_value() {
+        let any = JsonConstraint::any();
+        for text in [
+            "{}",
+            "[]",
+            " {\"a\": [1, -2.5e+3, true, null, \"x\\u00e9\\n\"], \"b\": {}}",
+            "\"text\"",
+            "0",
+            "false",
+        ] {
+            assert!(accepts(&any, text), "{}", text);
+        }
+        for text in [
+            "{",
+            "[1,]",
+            "{\"a\" 1}",
+            "01",
+            "-",
+            "1.",
+            "tru",
+            "\"a",
+            "nul",
+        ] {
+            assert!(!accepts(&any, text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn tracks_completion() {
+        let mut constraint = JsonConstraint::any();
+        constraint.advance(b"12").unwrap();
+        assert!(constraint.can_finish());
+        assert!(!constraint.is_finished());
+        assert!(constraint.allows(b"3"));
+        assert!(!constraint.allows(b"x"));
+        assert!(!constraint.allows(b""));
+
+        let mut constraint = JsonConstraint::object();
+        constraint.advance(b"{\"a\": 1").unwrap();
+        assert!(!constraint.can_finish());
+        constraint.advance(b"}").unwrap();
+        assert!(constraint.is_finished());
+        assert!(!constraint.allows(b" "));
+        assert!(constraint.advance(b"}").is_err());
+    }
+
+    #[test]
+    fn object_mode_only_accepts_objects() {
+        let object = JsonConstraint::object();
+        assert!(accepts(&object, "{\"a\": [1, {}]}"));
+        assert!(!object.allows(b"["));
+        assert!(!object.allows(b"1"));
+    }
+
+    #[test]
+    fn enforces_declared_properties() {
+        let person = schema(json!({
+            "type": "object",
+            "properties": {"name": {"type": "string"}, "age": {"type": "integer"}},
+            "required": ["name"],
+        }));
+        assert!(accepts(&person, "{\"name\": \"Ann\", \"age\": 30}"));
+        assert!(accepts(&person, "{\"age\": 30, \"name\": \"Ann\"}"));
+        // `required` must be present before the object closes.
+        assert!(!accepts(&person, "{}"));
+        assert!(!accepts(&person, "{\"age\": 30}"));
+        // Only declared keys, each once.
+        assert!(!person.allows(b"{\"x"));
+        assert!(!person.allows(b"{\"name\": \"a\", \"name\""));
+        assert!(!accepts(&person, "{\"name\": \"Ann\", \"age\": 1.5}"));
+        assert!(!accepts(&person, "{\"name\": 1}"));
+        // No comma once every declared key was used.
+        assert!(!person.allows(b"{\"name\": \"a\", \"age\": 1,"));
+    }
+
+    #[test]
+    fn enforces_items_enums_and_types() {
+        let tags = schema(json!({"type": "array", "items": {"enum": ["a", "b", 3]}}));
+        assert!(accepts(&tags, "[\"a\", 3, \"b\"]"));
+        assert!(!accepts(&tags, "[\"c\"]"));
+        assert!(!accepts(&tags, "[4]"));
+
+        let nullable = schema(json!({"type": ["string", "null"]}));
+        assert!(accepts(&nullable, "null"));
+        assert!(accepts(&nullable, "\"x\""));
+        assert!(!accepts(&nullable, "1"));
+
+        assert!(accepts(&schema(json!({"const": {"k": 1}})), "{\"k\":1}"));
+        assert!(accepts(
+            &schema(json!({"allOf": [{"type": "boolean"}]})),
+            "true"
+        ));
+    }
+
+    #[test]
+    fn follows_every_union_branch() {
+        let numeric = schema(json!({"anyOf": [{"type": "integer"}, {"type": "number"}]}));
+        assert!(accepts(&numeric, "1"));
+        assert!(accepts(&numeric, "1.5"));
+
+        let strings = schema(json!({"anyOf": [{"enum": ["a"]}, {"type": "string"}]}));
+        assert!(accepts(&strings, "\"a\""));
+        assert!(accepts(&strings, "\"b\""));
+
+        let objects = schema(json!({"oneOf": [
+            {"type": "object", "properties": {"a": {"type": "integer"}}, "required": ["a"]},
+            {"type": "object", "properties": {"b": {"type": "integer"}}, "required": ["b"]},
+        ]}));
+        assert!(accepts(&objects, "{\"a\": 1}"));
+        assert!(accepts(&objects, "{\"b\": 1}"));
+        assert!(!accepts(&objects, "{\"a\": 1, \"b\": 1}"));
+
+        assert!(!accepts(
+            &schema(json!({"anyOf": [{"type": "integer"}, {"type": "string"}]})),
+            "true"
+        ));
+    }
+
+    #[test]
+    fn union_branches_do_not_multiply() {
+        let numbers = schema(json!({
+            "type": "array",
+            "items": {"anyOf": [{"type": "integer"}, {"type": "number"}]},
+        }));
+        let mut constraint = numbers.clone();
+        constraint.advance(b"[").unwrap();
+        for _ in 0..64 {
+            constraint.advance(b"1,").unwrap();
+            assert_eq!(constraint.parsers.len(), 1);
+        }
+        assert!(accepts(&constraint, "2.5]"));
+        assert!(accepts(&numbers, "[1, 2.5, 3]"));
+    }
+
+    #[test]
+    fn rejects_invalid_utf8_in_strings() {
+        let any = JsonConstraint::any();
+        assert!(accepts(&any, "\"é€𝄞\""));
+        assert!(!any.allows(&[b'"', 0xC3, 0x28]));
+        // Overlong encodings, surrogates and code points past U+10FFFF.
+        assert!(!any.allows(&[b'"', 0xC0, 0x80]));
+        assert!(!any.allows(&[b'"', 0xE0, 0x80]));
+        assert!(!any.allows(&[b'"', 0xED, 0xA0]));
+        assert!(!any.allows(&[b'"', 0xF4, 0x90]));
+        assert!(!any.allows(&[b'"', 0x80]));
+        // A character may be split across tokens.
+        let mut constraint = any.clone();
+        constraint.advance(&[b'"', 0xE2]).unwrap();
+        assert!(!constraint.allows(b"\""));
+        constraint.advance(&[0x82, 0xAC, b'"']).unwrap();
+        assert!(constraint.is_finished());
+    }
+
+    #[test]
+    fn rejects_control_characters_and_bad_escapes() {
+        let any = JsonConstraint::any();
+        assert!(!any.allows(b"\"a\nb"));
+        assert!(!any.allows(b"\"\\x"));
+        assert!(!any.allows(b"\"\\u12g"));
+        assert!(accepts(&any, "\"\\\"\\\\\\/\\b\\f\\n\\r\\t\\u00AF\""));
+    }
+
+    #[test]
+    fn limits_whitespace_runs_outside_strings() {
+        let any = JsonConstraint::any();
+        let indent = " ".repeat(MAX_WHITESPACE_RUN);
+        assert!(accepts(&any, format!("{}[{}1]", indent, indent)));
+        assert!(!any.allows(format!("{} ", indent).as_bytes()));
+        assert!(accepts(&any, format!("\"{}{}\"", indent, indent)));
+    }
+
+    #[test]
+    fn rejects_unsupported_schemas() {
+        assert!(JsonConstraint::from_schema(&json!({"type": "date"})).is_err());
+        assert!(JsonConstraint::from_schema(&json!({"type": [1]})).is_err());
+        assert!(JsonConstraint::from_schema(&json!(false)).is_err());
+        assert!(JsonConstraint::from_schema(&json!(true)).is_ok());
+    }
+
+    #[test]
+    fn rejects_unsupported_keywords() {
+        for unsupported in [
+            json!({"$ref": "#/definitions/item"}),
+            json!({"type": "object", "properties": {"a": {"$ref": "#"}}}),
+            json!({"not": {"type": "string"}}),
+            json!({"if": {"type": "string"}, "then": {"const": "a"}}),
+            json!({"allOf": [{"type": "number"}, {"type": "integer"}]}),
+            json!({"allOf": []}),
+        ] {
+            assert!(
+                JsonConstraint::from_schema(&unsupported).is_err(),
+                "{}",
+                unsupported
+            );
+        }
+        // Value bounds are not enforced but do not fail the schema.
+        assert!(JsonConstraint::from_schema(&json!({"type": "integer", "minimum": 0})).is_ok());
+    }
+
+    #[test]
+    fn allowed_tokens_match_allows() {
+        let vocabulary = TokenVocabulary::new(
+            [
+                "{", "{\"", "\"", "name", "\":", " \"", "Ann", "\"}", "}", " ", "1", "x",
+            ]
+            .iter()
+            .map(|token| token.as_bytes().to_vec())
+            .collect(),
+            Vec::new(),
+        );
+        let index = TokenIndex::new(&vocabulary);
+        let mut person = schema(json!({
+            "type": "object",
+            "properties": {"name": {"type": "string"}},
+            "required": ["name"],
+        }));
+        for prefix in ["", "{", "{\"name\":", "{\"name\": \"Ann"] {
+            let mut constraint = person.clone();
+            constraint.advance(prefix.as_bytes()).unwrap();
+            let mut allowed = constraint.allowed_tokens(&index);
+            allowed.sort_unstable();
+            let expected: Vec<i32> = (0..12)
+                .filter(|&id| constraint.allows(vocabulary.token_bytes(id)))
+                .collect();
+            assert_eq!(allowed, expected, "after {:?}", prefix);
+        }
+        let mut allowed = person.allowed_tokens(&index);
+        allowed.sort_unstable();
+        assert_eq!(allowed, [0, 1, 9]);
+    }
+}
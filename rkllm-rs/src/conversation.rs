@@ -0,0 +1,147 @@
+use crate::prelude::{
+    ChatMessage, ChatTemplate, LLMConfig, LLMHandle, RKLLMLoraAdapter, RkllmError,
+    RuntimeChatTemplate,
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+pub const CONVERSATION_FORMAT_VERSION: u32 = 1;
+
+// Sampling settings are fixed at init, so they are saved to rebuild an equivalent `LLMConfig`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SamplingConfig {
+    pub max_context_len: i32,
+    pub max_new_tokens: i32,
+    pub top_k: i32,
+    pub n_keep: i32,
+    pub top_p: f32,
+    pub temperature: f32,
+    pub repeat_penalty: f32,
+    pub frequency_penalty: f32,
+    pub presence_penalty: f32,
+    pub mirostat: i32,
+    pub mirostat_tau: f32,
+    pub mirostat_eta: f32,
+}
+
+impl SamplingConfig {
+    pub fn apply_to(&self, config: &mut LLMConfig) {
+        config.max_context_len = self.max_context_len;
+        config.max_new_tokens = self.max_new_tokens;
+        config.top_k = self.top_k;
+        config.n_keep = self.n_keep;
+        config.top_p = self.top_p;
+        config.temperature = self.temperature;
+        config.repeat_penalty = self.repeat_penalty;
+        config.frequency_penalty = self.frequency_penalty;
+        config.presence_penalty = self.presence_penalty;
+        config.mirostat = self.mirostat;
+        config.mirostat_tau = self.mirostat_tau;
+        config.mirostat_eta = self.mirostat_eta;
+    }
+}
+
+impl From<&LLMConfig> for SamplingConfig {
+    fn from(config: &LLMConfig) -> Self {
+        Self {
+            max_context_len: config.max_context_len,
+            max_new_tokens: config.max_new_tokens,
+            top_k: config.top_k,
+            n_keep: config.n_keep,
+            top_p: config.top_p,
+            temperature: config.temperature,
+            repeat_penalty: config.repeat_penalty,
+            frequency_penalty: config.frequency_penalty,
+            presence_penalty: config.presence_penalty,
+            mirostat: config.mirostat,
+            mirostat_tau: config.mirostat_tau,
+            mirostat_eta: config.mirostat_eta,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedExchange {
+    pub first_turn: usize,
+    pub kv_start: i32,
+    pub kv_end: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedConversation {
+    pub version: u32,
+    pub model_path: Option<String>,
+    pub system_prompt: Option<String>,
+    pub summary: Option<String>,
+    pub enable_thinking: bool,
+    pub messages: Vec<ChatMessage>,
+    // KV cache ranges of the turns when the conversation was saved. Restoring replays the
+    // history instead.
+    pub exchanges: Vec<SavedExchange>,
+    pub runtime_chat_template: Option<RuntimeChatTemplate>,
+    pub chat_template: Option<ChatTemplate>,
+    pub sampling: SamplingConfig,
+    pub lora_adapter: Option<RKLLMLoraAdapter>,
+    pub prompt_cache_path: Option<String>,
+}
+
+impl SavedConversation {
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), RkllmError> {
+        let path = path.as_ref();
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json).map_err(|err| RkllmError::io(path, err))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RkllmError> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path).map_err(|err| RkllmError::io(path, err))?;
+        let saved: SavedConversation = serde_json::from_str(&json)?;
+        saved.check_version()?;
+        Ok(saved)
+    }
+
+    pub fn check_version(&self) -> Result<(), RkllmError> {
+        if self.version != CONVERSATION_FORMAT_VERSION {
+            return Err(RkllmError::invalid_argument(format!(
+                "unsupported conversation format version {} (expected {})",
+                self.version, CONVERSATION_FORMAT_VERSION
+            )));
+        }
+        Ok(())
+    }
+
+    // The prompt cache and KV positions are only meaningful for the model that produced them.
+    pub fn check_model(&self, handle: &LLMHandle) -> Result<(), RkllmError> {
+        let (Some(saved), Some(current)) = (self.model_path.as_deref(), handle.model_path()) else {
+            return Ok(());
+        };
+        if same_path(saved, current) {
+            return Ok(());
+        }
+        Err(RkllmError::invalid_argument(format!(
+            "conversation was saved with model {} but the handle runs {}",
+            saved, current
+        )))
+    }
+
+    // Starting point for the config of a handle to restore this conversation into.
+    pub fn llm_config(&self) -> LLMConfig {
+        let mut config = LLMConfig {
+            model_path: self.model_path.clone(),
+            ..LLMConfig::default()
+        };
+        self.sampling.apply_to(&mut config);
+        config
+    }
+}
+
+fn same_path(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
@@ -0,0 +1,105 @@
+use std::ffi::NulError;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RkllmError {
+    Init { code: i32 },
+    Run { api: &'static str, code: i32 },
+    RunIncomplete { api: &'static str },
+    InvalidArgument(String),
+    HandleDestroyed,
+    UnsupportedInput(&'static str),
+    Callback,
+    Template(String),
+    ContextBudgetExceeded { required: usize, budget: usize },
+    ToolIterationsExceeded { max_iterations: usize },
+    InvalidToolCall { name: String, problems: Vec<String> },
+    NoAllowedToken,
+    Io { path: PathBuf, source: io::Error },
+    Json(serde_json::Error),
+}
+
+impl RkllmError {
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            RkllmError::Init { code } | RkllmError::Run { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn invalid_argument(message: impl Into<String>) -> Self {
+        RkllmError::InvalidArgument(message.into())
+    }
+
+    pub(crate) fn io(path: &Path, source: io::Error) -> Self {
+        RkllmError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for RkllmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RkllmError::Init { code } => write!(f, "rkllm_init returned non-zero: {}", code),
+            RkllmError::Run { api, code } => write!(f, "{} returned non-zero: {}", api, code),
+            RkllmError::RunIncomplete { api } => write!(
+                f,
+                "{} returned before terminal callback; callback context remains owned by runtime",
+                api
+            ),
+            RkllmError::InvalidArgument(message) => write!(f, "invalid argument: {}", message),
+            RkllmError::HandleDestroyed => write!(f, "rkllm handle is already destroyed"),
+            RkllmError::UnsupportedInput(message) => write!(f, "unsupported input: {}", message),
+            RkllmError::Callback => write!(f, "runtime reported an error through the callback"),
+            RkllmError::Template(message) => write!(f, "chat template error: {}", message),
+            RkllmError::ContextBudgetExceeded { required, budget } => write!(
+                f,
+                "conversation needs {} tokens but the context budget is {}",
+                required, budget
+            ),
+            RkllmError::ToolIterationsExceeded { max_iterations } => write!(
+                f,
+                "model kept calling tools after {} iterations",
+                max_iterations
+            ),
+            RkllmError::InvalidToolCall { name, problems } => {
+                write!(f, "invalid call to tool {}: {}", name, problems.join("; "))
+            }
+            RkllmError::NoAllowedToken => {
+                write!(f, "no token in the vocabulary satisfies the constraint")
+            }
+            RkllmError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            RkllmError::Json(err) => write!(f, "invalid JSON: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RkllmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RkllmError::Io { source, .. } => Some(source),
+            RkllmError::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<NulError> for RkllmError {
+    fn from(err: NulError) -> Self {
+        RkllmError::InvalidArgument(format!(
+            "string contains an interior NUL byte at position {}",
+            err.nul_position()
+        ))
+    }
+}
+
+impl From<serde_json::Error> for RkllmError {
+    fn from(err: serde_json::Error) -> Self {
+        RkllmError::Json(err)
+    }
+}
@@ -0,0 +1,360 @@
+use crate::prelude::{
+    LLMHandle, RKLLMInferParam, RKLLMInput, RKLLMPerfStatData, RkllmError, RkllmEvent,
+};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::executor::block_on;
+use futures::stream::{FusedStream, Stream};
+use futures::StreamExt;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+const ABORT_WAIT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub text: String,
+    pub token_id: i32,
+}
+
+// Yielded by `Generation::events` and `TokenStream::events`; the run's perf stats arrive as the
+// last item.
+#[derive(Debug, Clone)]
+pub enum GenerationEvent {
+    Token(Token),
+    Finished(RKLLMPerfStatData),
+}
+
+pub(crate) enum GenerationMessage {
+    Token(Token),
+    Finished(RKLLMPerfStatData),
+    Error,
+}
+
+// Set once the runtime is done with a run: on its terminal callback, or when the callback
+// context is released without one.
+#[derive(Default)]
+pub(crate) struct RunDone {
+    done: Mutex<bool>,
+    signal: Condvar,
+}
+
+impl RunDone {
+    fn set(&self) {
+        if let Ok(mut done) = self.done.lock() {
+            *done = true;
+        }
+        self.signal.notify_all();
+    }
+
+    fn wait(&self, timeout: Duration) {
+        if let Ok(done) = self.done.lock() {
+            let _ = self.signal.wait_timeout_while(done, timeout, |done| !*done);
+        }
+    }
+}
+
+struct SetOnDrop(Arc<RunDone>);
+
+impl Drop for SetOnDrop {
+    fn drop(&mut self) {
+        self.0.set();
+    }
+}
+
+pub(crate) fn forward_events(
+    sender: UnboundedSender<GenerationMessage>,
+    done: Arc<RunDone>,
+) -> impl FnMut(RkllmEvent<'_>) + Send + Sync + 'static {
+    let done = SetOnDrop(done);
+    move |event: RkllmEvent<'_>| {
+        let message = match event {
+            RkllmEvent::Token { text, token_id } => GenerationMessage::Token(Token {
+                text: text.into_owned(),
+                token_id,
+            }),
+            RkllmEvent::Finished { perf } => GenerationMessage::Finished(perf),
+            RkllmEvent::Error => GenerationMessage::Error,
+            RkllmEvent::Waiting | RkllmEvent::HiddenLayer(_) | RkllmEvent::Logits(_) => return,
+        };
+        let terminal = !matches!(message, GenerationMessage::Token(_));
+        // The receiver may already be gone if the consumer stopped early.
+        let _ = sender.unbounded_send(message);
+        if terminal {
+            done.0.set();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum GenerationState {
+    Running,
+    Finished(RKLLMPerfStatData),
+    Failed,
+}
+
+impl GenerationState {
+    pub(crate) fn is_terminal(&self) -> bool {
+        !matches!(self, GenerationState::Running)
+    }
+
+    pub(crate) fn perf(&self) -> Option<RKLLMPerfStatData> {
+        match self {
+            GenerationState::Finished(perf) => Some(*perf),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn next_item(
+        &mut self,
+        message: Option<GenerationMessage>,
+    ) -> Option<Result<Token, RkllmError>> {
+        match self.next_event(message) {
+            Ok(GenerationEvent::Token(token)) => Some(Ok(token)),
+            Ok(GenerationEvent::Finished(_)) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+
+    pub(crate) fn next_event(
+        &mut self,
+        message: Option<GenerationMessage>,
+    ) -> Result<GenerationEvent, RkllmError> {
+        match message {
+            Some(GenerationMessage::Token(token)) => Ok(GenerationEvent::Token(token)),
+            Some(GenerationMessage::Finished(perf)) => {
+                *self = GenerationState::Finished(perf);
+                Ok(GenerationEvent::Finished(perf))
+            }
+            Some(GenerationMessage::Error) => {
+                *self = GenerationState::Failed;
+                Err(RkllmError::Callback)
+            }
+            None => {
+                *self = GenerationState::Failed;
+                Err(RkllmError::RunIncomplete {
+                    api: "rkllm_run_async",
+                })
+            }
+        }
+    }
+}
+
+pub(crate) struct StartedRun {
+    receiver: UnboundedReceiver<GenerationMessage>,
+    aborted: Arc<AtomicBool>,
+    done: Arc<RunDone>,
+}
+
+pub(crate) fn start_generation(
+    handle: &LLMHandle,
+    rkllm_input: RKLLMInput,
+    rkllm_infer_params: Option<RKLLMInferParam>,
+) -> Result<StartedRun, RkllmError> {
+    let (sender, receiver) = mpsc::unbounded();
+    let done = Arc::new(RunDone::default());
+    let aborted = handle.start_run_async(
+        rkllm_input,
+        rkllm_infer_params,
+        forward_events(sender, done.clone()),
+    )?;
+    Ok(StartedRun {
+        receiver,
+        aborted,
+        done,
+    })
+}
+
+pub struct Generation<'a> {
+    handle: &'a LLMHandle,
+    receiver: UnboundedReceiver<GenerationMessage>,
+    aborted: Arc<AtomicBool>,
+    done: Arc<RunDone>,
+    state: GenerationState,
+}
+
+impl<'a> Generation<'a> {
+    pub fn is_finished(&self) -> bool {
+        self.state.is_terminal()
+    }
+
+    pub fn perf(&self) -> Option<RKLLMPerfStatData> {
+        self.state.perf()
+    }
+
+    // Like iterating the tokens, but ends with `GenerationEvent::Finished` carrying the perf
+    // stats.
+    pub fn events(self) -> GenerationEvents<'a> {
+        GenerationEvents { generation: self }
+    }
+
+    // Aborts this generation; a no-op once another run has taken over the handle.
+    pub(crate) fn abort(&self) -> Result<(), RkllmError> {
+        self.handle.abort_run(&self.aborted)
+    }
+
+    pub(crate) fn was_aborted(&self) -> bool {
+        self.aborted.load(Ordering::Acquire)
+    }
+}
+
+impl Iterator for Generation<'_> {
+    type Item = Result<Token, RkllmError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.state.is_terminal() {
+            return None;
+        }
+        let message = block_on(self.receiver.next());
+        self.state.next_item(message)
+    }
+}
+
+impl Drop for Generation<'_> {
+    fn drop(&mut self) {
+        if self.state.is_terminal() {
+            return;
+        }
+        if self.abort().is_err() {
+            return;
+        }
+        // Wait for the terminal callback so the handle is idle for the next run, but never
+        // longer than `ABORT_WAIT` in case the runtime does not deliver one.
+        self.done.wait(ABORT_WAIT);
+    }
+}
+
+pub struct GenerationEvents<'a> {
+    generation: Generation<'a>,
+}
+
+impl GenerationEvents<'_> {
+    pub fn perf(&self) -> Option<RKLLMPerfStatData> {
+        self.generation.perf()
+    }
+}
+
+impl Iterator for GenerationEvents<'_> {
+    type Item = Result<GenerationEvent, RkllmError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let generation = &mut self.generation;
+        if generation.state.is_terminal() {
+            return None;
+        }
+        let message = block_on(generation.receiver.next());
+        Some(generation.state.next_event(message))
+    }
+}
+
+pub struct TokenStream<'a> {
+    handle: &'a LLMHandle,
+    receiver: UnboundedReceiver<GenerationMessage>,
+    aborted: Arc<AtomicBool>,
+    state: GenerationState,
+}
+
+impl<'a> TokenStream<'a> {
+    pub fn perf(&self) -> Option<RKLLMPerfStatData> {
+        self.state.perf()
+    }
+
+    // Like the token stream, but ends with `GenerationEvent::Finished` carrying the perf stats.
+    pub fn events(self) -> TokenEvents<'a> {
+        TokenEvents { stream: self }
+    }
+}
+
+impl Stream for TokenStream<'_> {
+    type Item = Result<Token, RkllmError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.state.is_terminal() {
+            return Poll::Ready(None);
+        }
+        this.receiver
+            .poll_next_unpin(cx)
+            .map(|message| this.state.next_item(message))
+    }
+}
+
+impl FusedStream for TokenStream<'_> {
+    fn is_terminated(&self) -> bool {
+        self.state.is_terminal()
+    }
+}
+
+impl Drop for TokenStream<'_> {
+    fn drop(&mut self) {
+        // Unlike `Generation`, this never blocks waiting for the terminal callback because it
+        // may be dropped on an async executor thread.
+        if !self.state.is_terminal() {
+            let _ = self.handle.abort_run(&self.aborted);
+        }
+    }
+}
+
+pub struct TokenEvents<'a> {
+    stream: TokenStream<'a>,
+}
+
+impl TokenEvents<'_> {
+    pub fn perf(&self) -> Option<RKLLMPerfStatData> {
+        self.stream.perf()
+    }
+}
+
+impl Stream for TokenEvents<'_> {
+    type Item = Result<GenerationEvent, RkllmError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let stream = &mut self.get_mut().stream;
+        if stream.state.is_terminal() {
+            return Poll::Ready(None);
+        }
+        stream
+            .receiver
+            .poll_next_unpin(cx)
+            .map(|message| Some(stream.state.next_event(message)))
+    }
+}
+
+impl FusedStream for TokenEvents<'_> {
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated()
+    }
+}
+
+impl LLMHandle {
+    pub fn stream(
+        &self,
+        rkllm_input: RKLLMInput,
+        rkllm_infer_params: Option<RKLLMInferParam>,
+    ) -> Result<TokenStream<'_>, RkllmError> {
+        let run = start_generation(self, rkllm_input, rkllm_infer_params)?;
+        Ok(TokenStream {
+            handle: self,
+            receiver: run.receiver,
+            aborted: run.aborted,
+            state: GenerationState::Running,
+        })
+    }
+
+    pub fn generate(
+        &self,
+        rkllm_input: RKLLMInput,
+        rkllm_infer_params: Option<RKLLMInferParam>,
+    ) -> Result<Generation<'_>, RkllmError> {
+        let run = start_generation(self, rkllm_input, rkllm_infer_params)?;
+        Ok(Generation {
+            handle: self,
+            receiver: run.receiver,
+            aborted: run.aborted,
+            done: run.done,
+            state: GenerationState::Running,
+        })
+    }
+}
@@ -0,0 +1,355 @@
+use crate::prelude::{ChatMessage, ChatRenderer, LLMHandle, RkllmError};
+use minijinja::{context, Environment, Error, ErrorKind};
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+const TEMPLATE_NAME: &str = "chat_template";
+const TOKENIZER_CONFIG_FILE: &str = "tokenizer_config.json";
+const CHAT_TEMPLATE_FILE: &str = "chat_template.jinja";
+
+#[derive(Serialize)]
+struct TemplateMessage<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+// Renders the Hugging Face `chat_template` of a model without network access.
+#[derive(Debug)]
+pub struct JinjaTemplate {
+    env: Environment<'static>,
+    bos_token: String,
+    eos_token: String,
+    tools: Option<Value>,
+}
+
+impl JinjaTemplate {
+    pub fn new(source: impl Into<String>) -> Result<Self, RkllmError> {
+        let mut env = Environment::new();
+        // Same whitespace handling as `transformers`' template sandbox.
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+        env.add_function("raise_exception", raise_exception);
+        env.add_template_owned(TEMPLATE_NAME, source.into())
+            .map_err(template_error)?;
+        Ok(Self {
+            env,
+            bos_token: String::new(),
+            eos_token: String::new(),
+            tools: None,
+        })
+    }
+
+    // Accepts a model directory, a `tokenizer_config.json` or a standalone `.jinja` file. In a
+    // directory `chat_template.jinja` takes precedence over the template embedded in the config,
+    // as it does in `transformers`.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, RkllmError> {
+        let path = path.as_ref();
+        if !path.is_dir() {
+            return if path.extension().is_some_and(|ext| ext == "json") {
+                Self::from_tokenizer_config(path)
+            } else {
+                Self::new(read_file(path)?)
+            };
+        }
+
+        let config_path = path.join(TOKENIZER_CONFIG_FILE);
+        let template_path = path.join(CHAT_TEMPLATE_FILE);
+        match (config_path.is_file(), template_path.is_file()) {
+            (true, true) => {
+                let config = read_tokenizer_config(&config_path)?;
+                Self::new(read_file(&template_path)?).map(|template| template.with_config(&config))
+            }
+            (true, false) => Self::from_tokenizer_config(&config_path),
+            (false, true) => Self::new(read_file(&template_path)?),
+            (false, false) => Err(RkllmError::invalid_argument(format!(
+                "{} contains neither {} nor {}",
+                path.display(),
+                TOKENIZER_CONFIG_FILE,
+                CHAT_TEMPLATE_FILE
+            ))),
+        }
+    }
+
+    pub fn from_tokenizer_config(path: impl AsRef<Path>) -> Result<Self, RkllmError> {
+        let path = path.as_ref();
+        let config = read_tokenizer_config(path)?;
+        let source = match config.get("chat_template") {
+            Some(Value::String(source)) => Some(source.as_str()),
+            // Configs with several named templates keep the chat one under "default".
+            Some(Value::Array(templates)) => templates
+                .iter()
+                .find(|template| template["name"] == "default")
+                .or_else(|| templates.first())
+                .and_then(|template| template["template"].as_str()),
+            _ => None,
+        }
+        .ok_or_else(|| {
+            RkllmError::invalid_argument(format!("{} has no chat_template", path.display()))
+        })?;
+        Self::new(source).map(|template| template.with_config(&config))
+    }
+
+    pub fn with_bos_token(mut self, bos_token: impl Into<String>) -> Self {
+        self.bos_token = bos_token.into();
+        self
+    }
+
+    pub fn with_eos_token(mut self, eos_token: impl Into<String>) -> Self {
+        self.eos_token = eos_token.into();
+        self
+    }
+
+    pub fn with_tools<T: Serialize>(mut self, tools: &T) -> Result<Self, RkllmError> {
+        let tools = serde_json::to_value(tools)?;
+        self.tools = Some(tools);
+        Ok(self)
+    }
+
+    pub fn bos_token(&self) -> &str {
+        &self.bos_token
+    }
+
+    pub fn eos_token(&self) -> &str {
+        &self.eos_token
+    }
+
+    pub fn render_with_tools(
+        &self,
+        messages: &[ChatMessage],
+        tools: Option<&Value>,
+        add_generation_prompt: bool,
+        enable_thinking: bool,
+    ) -> Result<String, RkllmError> {
+        let template = self
+            .env
+            .get_template(TEMPLATE_NAME)
+            .map_err(template_error)?;
+        let messages: Vec<_> = messages
+            .iter()
+            .map(|message| TemplateMessage {
+                role: message.role.as_str(),
+                content: &message.content,
+            })
+            .collect();
+        template
+            .render(context! {
+                messages => messages,
+                tools => tools.or(self.tools.as_ref()),
+                add_generation_prompt => add_generation_prompt,
+                enable_thinking => enable_thinking,
+                bos_token => self.bos_token,
+                eos_token => self.eos_token,
+            })
+            .map_err(template_error)
+    }
+
+    fn with_config(mut self, config: &Value) -> Self {
+        self.bos_token = special_token(config, "bos_token");
+        self.eos_token = special_token(config, "eos_token");
+        self
+    }
+}
+
+impl ChatRenderer for JinjaTemplate {
+    fn render(
+        &self,
+        messages: &[ChatMessage],
+        add_generation_prompt: bool,
+        enable_thinking: bool,
+    ) -> Result<String, RkllmError> {
+        self.render_with_tools(messages, None, add_generation_prompt, enable_thinking)
+    }
+
+    // Templates emit their preamble from inside the template, so the messages are rendered after
+    // a placeholder exchange and the rendering of that exchange is cut off. Templates that do not
+    // render it as a prefix fall back to a full rendering.
+    fn render_continuation(
+        &self,
+        messages: &[ChatMessage],
+        add_generation_prompt: bool,
+        enable_thinking: bool,
+    ) -> Result<String, RkllmError> {
+        let opening = [ChatMessage::user(""), ChatMessage::assistant("")];
+        let continued = self
+            .render(&opening, false, enable_thinking)
+            .and_then(|prefix| {
+                let rendered = self.render(
+                    &[&opening[..], messages].concat(),
+                    add_generation_prompt,
+                    enable_thinking,
+                )?;
+                Ok(rendered.strip_prefix(&prefix).map(str::to_owned))
+            });
+        match continued {
+            Ok(Some(rendered)) => Ok(rendered),
+            _ => self.render(messages, add_generation_prompt, enable_thinking),
+        }
+    }
+}
+
+impl LLMHandle {
+    pub fn load_chat_template(&self, path: impl AsRef<Path>) -> Result<(), RkllmError> {
+        self.set_chat_renderer(JinjaTemplate::from_path(path)?)
+    }
+}
+
+fn raise_exception(message: String) -> Result<String, Error> {
+    Err(Error::new(ErrorKind::InvalidOperation, message))
+}
+
+fn template_error(err: Error) -> RkllmError {
+    RkllmError::Template(err.to_string())
+}
+
+fn read_file(path: &Path) -> Result<String, RkllmError> {
+    fs::read_to_string(path).map_err(|err| RkllmError::io(path, err))
+}
+
+fn read_tokenizer_config(path: &Path) -> Result<Value, RkllmError> {
+    Ok(serde_json::from_str(&read_file(path)?)?)
+}
+
+// Special tokens are either plain strings or `AddedToken` objects with a `content` field.
+fn special_token(config: &Value, key: &str) -> String {
+    match &config[key] {
+        Value::String(token) => token.clone(),
+        token => token["content"].as_str().unwrap_or_default().to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const CHATML: &str = "{% for message in messages %}<|im_start|>{{ message.role }}\n{{ message.content }}<|im_end|>\n{% endfor %}{% if add_generation_prompt %}<|im_start|>assistant\n{% endif %}";
+
+    // Emits BOS and a dated system block unless the conversation brings its own system turn, like
+    // the Llama 3.1 template.
+    const DATED: &str = "{{ bos_token }}{% if messages[0].role != 'system' %}<|system|>Today: 2024-07-26<|end|>{% endif %}{% for message in messages %}<|{{ message.role }}|>{{ message.content }}<|end|>{% endfor %}{% if add_generation_prompt %}<|assistant|>{% endif %}";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rkllm-jinja-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn continuation_of_chatml_is_the_new_turn() {
+        let template = JinjaTemplate::new(CHATML).unwrap();
+        let rendered = template
+            .render_continuation(&[ChatMessage::user("hi")], true, false)
+            .unwrap();
+        assert_eq!(
+            rendered,
+            "<|im_start|>user\nhi<|im_end|>\n<|im_start|>assistant\n"
+        );
+    }
+
+    #[test]
+    fn continuation_drops_bos_and_dated_preamble() {
+        let template = JinjaTemplate::new(DATED).unwrap().with_bos_token("<s>");
+        let messages = [ChatMessage::user("hi")];
+
+        assert_eq!(
+            template.render(&messages, true, false).unwrap(),
+            "<s><|system|>Today: 2024-07-26<|end|><|user|>hi<|end|><|assistant|>"
+        );
+        assert_eq!(
+            template
+                .render_continuation(&messages, true, false)
+                .unwrap(),
+            "<|user|>hi<|end|><|assistant|>"
+        );
+    }
+
+    #[test]
+    fn continuation_falls_back_to_a_full_render() {
+        // Renders only the last message, so the placeholder exchange is not a prefix.
+        let template =
+            JinjaTemplate::new("[{{ messages[-1].role }}] {{ messages[-1].content }}").unwrap();
+        assert_eq!(
+            template
+                .render_continuation(&[ChatMessage::user("hi")], false, false)
+                .unwrap(),
+            "[user] hi"
+        );
+    }
+
+    #[test]
+    fn tokenizer_config_provides_template_and_special_tokens() {
+        let dir = temp_dir("config");
+        let path = dir.join(TOKENIZER_CONFIG_FILE);
+        let config = serde_json::json!({
+            "chat_template": DATED,
+            "bos_token": { "content": "<s>", "lstrip": false },
+            "eos_token": "</s>",
+        });
+        fs::write(&path, config.to_string()).unwrap();
+
+        let template = JinjaTemplate::from_tokenizer_config(&path).unwrap();
+        assert_eq!(template.bos_token(), "<s>");
+        assert_eq!(template.eos_token(), "</s>");
+        assert!(template
+            .render(&[ChatMessage::system("be brief")], false, false)
+            .unwrap()
+            .starts_with("<s><|system|>be brief"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tokenizer_config_picks_the_default_named_template() {
+        let dir = temp_dir("named");
+        let path = dir.join(TOKENIZER_CONFIG_FILE);
+        let config = serde_json::json!({
+            "chat_template": [
+                { "name": "tool_use", "template": "tools" },
+                { "name": "default", "template": "chat" },
+            ],
+        });
+        fs::write(&path, config.to_string()).unwrap();
+
+        let template = JinjaTemplate::from_tokenizer_config(&path).unwrap();
+        assert_eq!(template.render(&[], false, false).unwrap(), "chat");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tokenizer_config_errors() {
+        let dir = temp_dir("errors");
+        let path = dir.join(TOKENIZER_CONFIG_FILE);
+        assert!(matches!(
+            JinjaTemplate::from_tokenizer_config(&path),
+            Err(RkllmError::Io { .. })
+        ));
+        fs::write(&path, "{").unwrap();
+        assert!(matches!(
+            JinjaTemplate::from_tokenizer_config(&path),
+            Err(RkllmError::Json(_))
+        ));
+        fs::write(&path, r#"{"bos_token": "<s>"}"#).unwrap();
+        assert!(matches!(
+            JinjaTemplate::from_tokenizer_config(&path),
+            Err(RkllmError::InvalidArgument(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn model_directory_prefers_the_jinja_file() {
+        let dir = temp_dir("directory");
+        let config = serde_json::json!({ "chat_template": "embedded", "eos_token": "</s>" });
+        fs::write(dir.join(TOKENIZER_CONFIG_FILE), config.to_string()).unwrap();
+        fs::write(dir.join(CHAT_TEMPLATE_FILE), "standalone {{ eos_token }}").unwrap();
+
+        let template = JinjaTemplate::from_path(&dir).unwrap();
+        assert_eq!(
+            template.render(&[], false, false).unwrap(),
+            "standalone </s>"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -4,17 +4,67 @@
 
 pub use rkllm_sys_rs::*;
 
+mod chat;
+mod completion;
+mod constrained;
+mod constraint;
+mod conversation;
+mod error;
+mod generation;
+#[cfg(feature = "local_config")]
+mod jinja;
+mod reasoning;
+#[cfg(feature = "regex")]
+mod regex_constraint;
+mod session_manager;
+mod template;
+mod tool_call;
+mod tool_runner;
+mod tool_spec;
+mod tool_validation;
+mod vocabulary;
+
 pub mod prelude {
-    use serde::Serialize;
+    pub use crate::chat::{ChatSession, Summarizer, TokenCounter, TruncationStrategy};
+    pub use crate::completion::{Completion, CompletionOptions, FinishReason};
+    pub use crate::constrained::SamplingParams;
+    pub use crate::constraint::{Constraint, JsonConstraint};
+    pub use crate::conversation::{
+        SamplingConfig, SavedConversation, SavedExchange, CONVERSATION_FORMAT_VERSION,
+    };
+    pub use crate::error::RkllmError;
+    pub use crate::generation::{
+        Generation, GenerationEvent, GenerationEvents, Token, TokenEvents, TokenStream,
+    };
+    #[cfg(feature = "local_config")]
+    pub use crate::jinja::JinjaTemplate;
+    pub use crate::reasoning::{
+        ReasoningChunk, ReasoningParser, ReasoningTokens, DEFAULT_REASONING_CLOSE_TAG,
+        DEFAULT_REASONING_OPEN_TAG,
+    };
+    #[cfg(feature = "regex")]
+    pub use crate::regex_constraint::RegexConstraint;
+    pub use crate::session_manager::{SessionManager, DEFAULT_SESSION_CAPACITY};
+    pub use crate::template::{ChatMessage, ChatRenderer, ChatTemplate, RuntimeChatTemplate};
+    pub use crate::tool_call::{parse_tool_calls, ToolCall, ToolCallFormat, ToolCallParser};
+    pub use crate::tool_runner::{
+        ToolErrorHandling, ToolHandler, ToolInvocation, ToolOutcome, ToolRun, ToolRunner,
+        ToolValidation, DEFAULT_MAX_TOOL_ITERATIONS, DEFAULT_TOOL_CALL_RETRIES,
+    };
+    #[cfg(feature = "tool_schema")]
+    pub use crate::tool_spec::parameters_schema;
+    pub use crate::tool_spec::{FunctionSpec, ToolSpec};
+    pub use crate::tool_validation::{validate_arguments, validate_tool_call};
+    pub use crate::vocabulary::{TokenIndex, TokenVocabulary, Vocabulary};
+    use serde::{Deserialize, Serialize};
+    use std::any::Any;
     use std::borrow::Cow;
     use std::ffi::{c_void, CStr, CString};
-    use std::io;
     use std::os::raw::c_char;
+    use std::panic::{self, AssertUnwindSafe};
     use std::ptr::null_mut;
     use std::sync::atomic::{AtomicBool, Ordering};
-    use std::sync::{Arc, Mutex};
-
-    type BoxError = Box<dyn std::error::Error + Send + Sync>;
+    use std::sync::{Arc, Mutex, PoisonError};
 
     #[derive(Debug, PartialEq, Eq, Clone, Copy)]
     pub enum LLMCallState {
@@ -135,35 +185,40 @@ pub mod prelude {
     impl Default for LLMConfig {
         fn default() -> Self {
             let raw = super::RKLLMParam::default();
-            Self {
-                model_path: None,
-                max_context_len: raw.max_context_len,
-                max_new_tokens: raw.max_new_tokens,
-                top_k: raw.top_k,
-                n_keep: raw.n_keep,
-                top_p: raw.top_p,
-                temperature: raw.temperature,
-                repeat_penalty: raw.repeat_penalty,
-                frequency_penalty: raw.frequency_penalty,
-                presence_penalty: raw.presence_penalty,
-                mirostat: raw.mirostat,
-                mirostat_tau: raw.mirostat_tau,
-                mirostat_eta: raw.mirostat_eta,
-                skip_special_token: raw.skip_special_token,
-                is_async: raw.is_async,
-                img_start: c_string_ptr_to_option(raw.img_start),
-                img_end: c_string_ptr_to_option(raw.img_end),
-                img_content: c_string_ptr_to_option(raw.img_content),
-                extend_param: raw.extend_param.into(),
-            }
+            unsafe { config_from_raw(&raw) }
+        }
+    }
+
+    unsafe fn config_from_raw(raw: &super::RKLLMParam) -> LLMConfig {
+        LLMConfig {
+            model_path: c_string_ptr_to_option(raw.model_path),
+            max_context_len: raw.max_context_len,
+            max_new_tokens: raw.max_new_tokens,
+            top_k: raw.top_k,
+            n_keep: raw.n_keep,
+            top_p: raw.top_p,
+            temperature: raw.temperature,
+            repeat_penalty: raw.repeat_penalty,
+            frequency_penalty: raw.frequency_penalty,
+            presence_penalty: raw.presence_penalty,
+            mirostat: raw.mirostat,
+            mirostat_tau: raw.mirostat_tau,
+            mirostat_eta: raw.mirostat_eta,
+            skip_special_token: raw.skip_special_token,
+            is_async: raw.is_async,
+            img_start: c_string_ptr_to_option(raw.img_start),
+            img_end: c_string_ptr_to_option(raw.img_end),
+            img_content: c_string_ptr_to_option(raw.img_content),
+            extend_param: raw.extend_param.into(),
         }
     }
 
     impl LLMConfig {
         pub fn with_model_path(model_path: impl Into<String>) -> Self {
-            let mut config = Self::default();
-            config.model_path = Some(model_path.into());
-            config
+            Self {
+                model_path: Some(model_path.into()),
+                ..Self::default()
+            }
         }
     }
 
@@ -193,7 +248,7 @@ pub mod prelude {
         }
     }
 
-    #[derive(Debug, Clone, Copy)]
+    #[derive(Debug, Clone, Copy, Default)]
     pub struct RKLLMPerfStatData {
         pub prefill_time_ms: f32,
         pub prefill_tokens: i32,
@@ -202,6 +257,18 @@ pub mod prelude {
         pub memory_usage_mb: f32,
     }
 
+    impl From<super::RKLLMPerfStat> for RKLLMPerfStatData {
+        fn from(value: super::RKLLMPerfStat) -> Self {
+            Self {
+                prefill_time_ms: value.prefill_time_ms,
+                prefill_tokens: value.prefill_tokens,
+                generate_time_ms: value.generate_time_ms,
+                generate_tokens: value.generate_tokens,
+                memory_usage_mb: value.memory_usage_mb,
+            }
+        }
+    }
+
     #[derive(Debug)]
     pub struct RKLLMResult<'a> {
         pub text: Cow<'a, str>,
@@ -259,18 +326,12 @@ pub mod prelude {
                 token_id: result.token_id,
                 last_hidden_layer,
                 logits,
-                perf: RKLLMPerfStatData {
-                    prefill_time_ms: result.perf.prefill_time_ms,
-                    prefill_tokens: result.perf.prefill_tokens,
-                    generate_time_ms: result.perf.generate_time_ms,
-                    generate_tokens: result.perf.generate_tokens,
-                    memory_usage_mb: result.perf.memory_usage_mb,
-                },
+                perf: result.perf.into(),
             }
         }
     }
 
-    #[derive(Debug, Clone)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct RKLLMLoraAdapter {
         pub lora_adapter_path: String,
         pub lora_adapter_name: String,
@@ -288,6 +349,17 @@ pub mod prelude {
     pub struct LLMHandle {
         handle: super::LLMHandle,
         is_destroyed: AtomicBool,
+        // Abort flag of the run currently on the handle, if any.
+        current_run: Arc<Mutex<Option<Arc<AtomicBool>>>>,
+        config: LLMConfig,
+        lora_adapters: Mutex<Vec<RKLLMLoraAdapter>>,
+        prompt_cache: Mutex<Option<String>>,
+        chat_renderer: Mutex<Option<Arc<dyn ChatRenderer>>>,
+        runtime_chat_template: Mutex<Option<RuntimeChatTemplate>>,
+        function_tools: Mutex<Option<serde_json::Value>>,
+        vocabulary: Mutex<Option<Arc<dyn Vocabulary>>>,
+        cross_attn_active: AtomicBool,
+        last_perf: Arc<Mutex<Option<RKLLMPerfStatData>>>,
         _owned_param_strings: InitParamStrings,
     }
 
@@ -298,6 +370,55 @@ pub mod prelude {
         fn handle(&mut self, result: Option<RKLLMResult<'_>>, state: LLMCallState);
     }
 
+    #[derive(Debug)]
+    pub enum RkllmEvent<'a> {
+        Token { text: Cow<'a, str>, token_id: i32 },
+        Waiting,
+        HiddenLayer(RKLLMResultLastHiddenLayerData<'a>),
+        Logits(RKLLMResultLogitsData<'a>),
+        Finished { perf: RKLLMPerfStatData },
+        Error,
+    }
+
+    impl<'a> RkllmEvent<'a> {
+        pub fn from_callback(result: Option<RKLLMResult<'a>>, state: LLMCallState) -> Option<Self> {
+            match state {
+                LLMCallState::Normal => {
+                    let result = result?;
+                    Some(if let Some(logits) = result.logits {
+                        RkllmEvent::Logits(logits)
+                    } else if let Some(hidden_layer) = result.last_hidden_layer {
+                        RkllmEvent::HiddenLayer(hidden_layer)
+                    } else {
+                        RkllmEvent::Token {
+                            text: result.text,
+                            token_id: result.token_id,
+                        }
+                    })
+                }
+                LLMCallState::Waiting => Some(RkllmEvent::Waiting),
+                LLMCallState::Finish => Some(RkllmEvent::Finished {
+                    perf: result.map(|result| result.perf).unwrap_or_default(),
+                }),
+                LLMCallState::Error => Some(RkllmEvent::Error),
+                LLMCallState::GetLastHiddenLayer => {
+                    result?.last_hidden_layer.map(RkllmEvent::HiddenLayer)
+                }
+            }
+        }
+    }
+
+    impl<F> RkllmCallbackHandler for F
+    where
+        F: FnMut(RkllmEvent<'_>),
+    {
+        fn handle(&mut self, result: Option<RKLLMResult<'_>>, state: LLMCallState) {
+            if let Some(event) = RkllmEvent::from_callback(result, state) {
+                self(event);
+            }
+        }
+    }
+
     #[derive(Debug, Default)]
     struct InitParamStrings {
         model_path: Option<CString>,
@@ -308,7 +429,7 @@ pub mod prelude {
 
     fn raw_param_from_config(
         config: &LLMConfig,
-    ) -> Result<(super::RKLLMParam, InitParamStrings), BoxError> {
+    ) -> Result<(super::RKLLMParam, InitParamStrings), RkllmError> {
         let strings = InitParamStrings {
             model_path: config
                 .model_path
@@ -374,6 +495,9 @@ pub mod prelude {
         infer_param: Option<Box<super::RKLLMInferParam>>,
         _role: CString,
         _prompt: Option<CString>,
+        _token_ids: Option<Vec<i32>>,
+        _embed: Option<EmbedInput>,
+        _multimodal: Option<(CString, Vec<f32>)>,
         _lora_adapter_name: Option<CString>,
         _lora_param: Option<Box<super::RKLLMLoraParam>>,
         _prompt_cache_path: Option<CString>,
@@ -381,41 +505,87 @@ pub mod prelude {
     }
 
     impl RunArguments {
-        fn new(input: RKLLMInput, infer_param: Option<RKLLMInferParam>) -> Result<Self, BoxError> {
-            let role = CString::new(match input.role {
-                RKLLMInputRole::User => "user",
-                RKLLMInputRole::Tool => "tool",
-            })?;
-
-            let prompt = match input.input_type {
-                RKLLMInputType::Prompt(prompt) => Some(CString::new(prompt)?),
-                RKLLMInputType::Token(_) => {
-                    return Err(Box::new(io::Error::new(
-                        io::ErrorKind::Unsupported,
-                        "RKLLM_INPUT_TOKEN is not wrapped yet",
-                    )))
+        fn new(
+            input: RKLLMInput,
+            infer_param: Option<RKLLMInferParam>,
+        ) -> Result<Self, RkllmError> {
+            let role = CString::new(input.role.as_str())?;
+
+            let mut prompt = None;
+            let mut token_ids = None;
+            let mut embed = None;
+            let mut multimodal = None;
+            let (input_type, raw_payload) = match input.input_type {
+                RKLLMInputType::Prompt(text) => {
+                    let text = prompt.insert(CString::new(text)?);
+                    (
+                        super::RKLLMInputType_RKLLM_INPUT_PROMPT,
+                        super::RKLLMInput__bindgen_ty_1 {
+                            prompt_input: text.as_ptr(),
+                        },
+                    )
                 }
-                RKLLMInputType::Embed(_) => {
-                    return Err(Box::new(io::Error::new(
-                        io::ErrorKind::Unsupported,
-                        "RKLLM_INPUT_EMBED is not wrapped yet",
-                    )))
+                RKLLMInputType::Token(ids) => {
+                    if ids.is_empty() {
+                        return Err(RkllmError::invalid_argument("token input is empty"));
+                    }
+                    let ids = token_ids.insert(ids);
+                    (
+                        super::RKLLMInputType_RKLLM_INPUT_TOKEN,
+                        super::RKLLMInput__bindgen_ty_1 {
+                            token_input: super::RKLLMTokenInput {
+                                input_ids: ids.as_mut_ptr(),
+                                n_tokens: ids.len(),
+                            },
+                        },
+                    )
                 }
-                RKLLMInputType::Multimodal(_) => {
-                    return Err(Box::new(io::Error::new(
-                        io::ErrorKind::Unsupported,
-                        "RKLLM_INPUT_MULTIMODAL is not wrapped yet",
-                    )))
+                RKLLMInputType::Embed(input) => {
+                    let input = embed.insert(input);
+                    (
+                        super::RKLLMInputType_RKLLM_INPUT_EMBED,
+                        super::RKLLMInput__bindgen_ty_1 {
+                            embed_input: super::RKLLMEmbedInput {
+                                embed: input.embed.as_mut_ptr(),
+                                n_tokens: input.n_tokens,
+                            },
+                        },
+                    )
+                }
+                RKLLMInputType::Messages(_) => {
+                    return Err(RkllmError::UnsupportedInput(
+                        "messages must be rendered before running",
+                    ))
+                }
+                RKLLMInputType::Multimodal(input) => {
+                    if input.n_image == 0 {
+                        return Err(RkllmError::invalid_argument(
+                            "multimodal input must contain at least one image",
+                        ));
+                    }
+                    let text = CString::new(input.prompt_with_placeholders()?)?;
+                    let (text, image_embed) = multimodal.insert((text, input.image_embed));
+                    (
+                        super::RKLLMInputType_RKLLM_INPUT_MULTIMODAL,
+                        super::RKLLMInput__bindgen_ty_1 {
+                            multimodal_input: super::RKLLMMultiModalInput {
+                                prompt: text.as_ptr() as *mut c_char,
+                                image_embed: image_embed.as_mut_ptr(),
+                                n_image_tokens: input.n_image_tokens,
+                                n_image: input.n_image,
+                                image_width: input.image_width,
+                                image_height: input.image_height,
+                            },
+                        },
+                    )
                 }
             };
 
             let raw_input = Box::new(super::RKLLMInput {
-                input_type: super::RKLLMInputType_RKLLM_INPUT_PROMPT,
+                input_type,
                 enable_thinking: input.enable_thinking,
                 role: role.as_ptr(),
-                __bindgen_anon_1: super::RKLLMInput__bindgen_ty_1 {
-                    prompt_input: prompt.as_ref().map_or(std::ptr::null(), |p| p.as_ptr()),
-                },
+                __bindgen_anon_1: raw_payload,
             });
 
             let mut lora_adapter_name = None;
@@ -464,6 +634,9 @@ pub mod prelude {
                 infer_param: raw_infer_param,
                 _role: role,
                 _prompt: prompt,
+                _token_ids: token_ids,
+                _embed: embed,
+                _multimodal: multimodal,
                 _lora_adapter_name: lora_adapter_name,
                 _lora_param: lora_param,
                 _prompt_cache_path: prompt_cache_path,
@@ -483,31 +656,81 @@ pub mod prelude {
     }
 
     struct InstanceData {
+        handle: super::LLMHandle,
         callback_handler: Arc<Mutex<dyn RkllmCallbackHandler + Send + Sync>>,
         finished: AtomicBool,
-        run_args: Option<RunArguments>,
+        errored: AtomicBool,
+        panic_payload: Mutex<Option<Box<dyn Any + Send>>>,
+        // Set for `run`, which re-raises a handler panic on the caller's thread.
+        reraise_panic: bool,
+        last_perf: Arc<Mutex<Option<RKLLMPerfStatData>>>,
+        aborted: Arc<AtomicBool>,
+        current_run: Arc<Mutex<Option<Arc<AtomicBool>>>>,
+        run_args: RunArguments,
     }
 
+    // `run_args` only points at allocations it owns, and `handle` is only used to call
+    // `rkllm_abort`, which the runtime allows from any thread.
+    unsafe impl Send for InstanceData {}
+    unsafe impl Sync for InstanceData {}
+
     impl InstanceData {
         fn new(
+            handle: &LLMHandle,
             callback_handler: impl RkllmCallbackHandler + Send + Sync + 'static,
-            run_args: Option<RunArguments>,
+            run_args: RunArguments,
+            reraise_panic: bool,
         ) -> Self {
+            let aborted = Arc::new(AtomicBool::new(false));
+            if let Ok(mut current_run) = handle.current_run.lock() {
+                *current_run = Some(Arc::clone(&aborted));
+            }
             Self {
+                handle: handle.handle,
                 callback_handler: Arc::new(Mutex::new(callback_handler)),
                 finished: AtomicBool::new(false),
+                errored: AtomicBool::new(false),
+                panic_payload: Mutex::new(None),
+                reraise_panic,
+                last_perf: Arc::clone(&handle.last_perf),
+                aborted,
+                current_run: Arc::clone(&handle.current_run),
                 run_args,
             }
         }
 
-        fn run_args_ptrs(
-            &mut self,
-        ) -> Result<(*mut super::RKLLMInput, *mut super::RKLLMInferParam), BoxError> {
-            let args = self
-                .run_args
-                .as_mut()
-                .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "missing run arguments"))?;
-            Ok((args.input_ptr(), args.infer_param_ptr()))
+        fn has_panicked(&self) -> bool {
+            self.panic_payload
+                .lock()
+                .map_or(true, |payload| payload.is_some())
+        }
+
+        fn store_panic(&self, payload: Box<dyn Any + Send>) {
+            if let Ok(mut slot) = self.panic_payload.lock() {
+                slot.get_or_insert(payload);
+            }
+        }
+
+        fn take_panic(&self) -> Option<Box<dyn Any + Send>> {
+            self.panic_payload.lock().ok()?.take()
+        }
+
+        // Detaches the run from the handle so a later `abort()` is not attributed to it.
+        fn end_run(&self) {
+            if let Ok(mut current_run) = self.current_run.lock() {
+                if current_run
+                    .as_ref()
+                    .is_some_and(|current| Arc::ptr_eq(current, &self.aborted))
+                {
+                    *current_run = None;
+                }
+            }
+        }
+
+        // The raw pointers target boxed allocations, so they stay valid after `self` is moved
+        // into an `Arc`.
+        fn run_args_ptrs(&mut self) -> (*mut super::RKLLMInput, *mut super::RKLLMInferParam) {
+            (self.run_args.input_ptr(), self.run_args.infer_param_ptr())
         }
     }
 
@@ -542,53 +765,121 @@ pub mod prelude {
         }
     }
 
-    fn status_to_result(api: &str, ret: i32) -> Result<(), BoxError> {
+    fn status_to_result(api: &'static str, ret: i32) -> Result<(), RkllmError> {
         if ret == 0 {
             Ok(())
         } else {
-            Err(Box::new(io::Error::new(
-                io::ErrorKind::Other,
-                format!("{} returned non-zero: {}", api, ret),
-            )))
+            Err(RkllmError::Run { api, code: ret })
         }
     }
 
     impl LLMHandle {
-        fn ensure_alive(&self) -> Result<(), BoxError> {
-            if self.handle.is_null() {
-                return Err(Box::new(io::Error::new(
-                    io::ErrorKind::Other,
-                    "rkllm handle is null",
-                )));
-            }
-            if self.is_destroyed.load(Ordering::Acquire) {
-                return Err(Box::new(io::Error::new(
-                    io::ErrorKind::Other,
-                    "rkllm handle is already destroyed",
-                )));
+        fn ensure_alive(&self) -> Result<(), RkllmError> {
+            if self.handle.is_null() || self.is_destroyed.load(Ordering::Acquire) {
+                return Err(RkllmError::HandleDestroyed);
             }
             Ok(())
         }
 
+        pub(crate) fn max_new_tokens(&self) -> i32 {
+            self.config.max_new_tokens
+        }
+
+        fn current_run(&self) -> Option<Arc<AtomicBool>> {
+            self.current_run.lock().ok()?.clone()
+        }
+
+        // Aborts only while the run that owns `aborted` is still the handle's current run, so a
+        // late call cannot stop a run started by someone else.
+        pub(crate) fn abort_run(&self, aborted: &Arc<AtomicBool>) -> Result<(), RkllmError> {
+            match self.current_run() {
+                Some(current) if Arc::ptr_eq(&current, aborted) => self.abort(),
+                _ => Ok(()),
+            }
+        }
+
+        // Roles the runtime cannot take as a string role, and message lists, are rendered
+        // through the chat renderer into a single prompt. When the run keeps history on top of a
+        // non-empty KV cache the messages continue a conversation, so they are rendered without
+        // the template's preamble.
+        fn prepare_input(
+            &self,
+            rkllm_input: RKLLMInput,
+            rkllm_infer_params: Option<&RKLLMInferParam>,
+        ) -> Result<RKLLMInput, RkllmError> {
+            let RKLLMInput {
+                input_type,
+                enable_thinking,
+                role,
+            } = rkllm_input;
+            let messages = match input_type {
+                RKLLMInputType::Prompt(content) => vec![ChatMessage::new(role, content)],
+                RKLLMInputType::Messages(messages) => messages,
+                RKLLMInputType::Multimodal(mut input) => {
+                    if input.placeholder.is_none() {
+                        input.placeholder = self
+                            .config()
+                            .img_content
+                            .clone()
+                            .filter(|content| !content.is_empty());
+                    }
+                    return Ok(RKLLMInput {
+                        input_type: RKLLMInputType::Multimodal(input),
+                        enable_thinking,
+                        role,
+                    });
+                }
+                input_type => {
+                    return Ok(RKLLMInput {
+                        input_type,
+                        enable_thinking,
+                        role,
+                    })
+                }
+            };
+
+            let Some(renderer) = self.chat_renderer() else {
+                return match <[ChatMessage; 1]>::try_from(messages) {
+                    Ok([message]) if message.role.is_native() => Ok(RKLLMInput {
+                        input_type: RKLLMInputType::Prompt(message.content),
+                        enable_thinking,
+                        role: message.role,
+                    }),
+                    _ => Err(RkllmError::UnsupportedInput(
+                        "system, assistant, custom roles and message lists need a chat renderer",
+                    )),
+                };
+            };
+            let add_generation_prompt = messages
+                .last()
+                .is_some_and(|message| message.role != RKLLMInputRole::Assistant);
+            let keep_history = rkllm_infer_params
+                .is_some_and(|params| matches!(params.keep_history, KeepHistory::KeepHistory));
+            let prompt = if keep_history && !self.kv_cache_is_empty()? {
+                renderer.render_continuation(&messages, add_generation_prompt, enable_thinking)?
+            } else {
+                renderer.render(&messages, add_generation_prompt, enable_thinking)?
+            };
+            Ok(RKLLMInput {
+                input_type: RKLLMInputType::Prompt(prompt),
+                enable_thinking,
+                role: RKLLMInputRole::User,
+            })
+        }
+
         pub fn run(
             &self,
             rkllm_input: RKLLMInput,
             rkllm_infer_params: Option<RKLLMInferParam>,
             user_data: impl RkllmCallbackHandler + Send + Sync + 'static,
-        ) -> Result<(), BoxError> {
+        ) -> Result<(), RkllmError> {
             self.ensure_alive()?;
 
+            let rkllm_input = self.prepare_input(rkllm_input, rkllm_infer_params.as_ref())?;
             let run_args = RunArguments::new(rkllm_input, rkllm_infer_params)?;
-            let mut instance_data = Arc::new(InstanceData::new(user_data, Some(run_args)));
-            let (input_ptr, infer_param_ptr) = {
-                let data = Arc::get_mut(&mut instance_data).ok_or_else(|| {
-                    io::Error::new(
-                        io::ErrorKind::Other,
-                        "failed to get mutable callback context",
-                    )
-                })?;
-                data.run_args_ptrs()?
-            };
+            let mut data = InstanceData::new(self, user_data, run_args, true);
+            let (input_ptr, infer_param_ptr) = data.run_args_ptrs();
+            let instance_data = Arc::new(data);
             let mut callback_context = CallbackContextGuard::new(&instance_data);
 
             let ret = unsafe {
@@ -604,13 +895,18 @@ pub mod prelude {
             if finished {
                 callback_context.disarm();
             }
+            instance_data.end_run();
 
+            if let Some(payload) = instance_data.take_panic() {
+                drop(callback_context);
+                panic::resume_unwind(payload);
+            }
             status_to_result("rkllm_run", ret)?;
             if !finished {
-                return Err(Box::new(io::Error::new(
-                    io::ErrorKind::Other,
-                    "rkllm_run returned before terminal callback; callback context remains owned by runtime",
-                )));
+                return Err(RkllmError::RunIncomplete { api: "rkllm_run" });
+            }
+            if instance_data.errored.load(Ordering::Acquire) {
+                return Err(RkllmError::Callback);
             }
             Ok(())
         }
@@ -620,7 +916,7 @@ pub mod prelude {
             rkllm_input: RKLLMInput,
             rkllm_infer_params: Option<RKLLMInferParam>,
             user_data: impl RkllmCallbackHandler + Send + Sync + 'static,
-        ) -> Result<(), BoxError> {
+        ) -> Result<(), RkllmError> {
             self.run(rkllm_input, rkllm_infer_params, user_data)
         }
 
@@ -629,20 +925,25 @@ pub mod prelude {
             rkllm_input: RKLLMInput,
             rkllm_infer_params: Option<RKLLMInferParam>,
             user_data: impl RkllmCallbackHandler + Send + Sync + 'static,
-        ) -> Result<(), BoxError> {
+        ) -> Result<(), RkllmError> {
+            self.start_run_async(rkllm_input, rkllm_infer_params, user_data)
+                .map(|_| ())
+        }
+
+        // Like `run_async`, returning the run's abort flag.
+        pub(crate) fn start_run_async(
+            &self,
+            rkllm_input: RKLLMInput,
+            rkllm_infer_params: Option<RKLLMInferParam>,
+            user_data: impl RkllmCallbackHandler + Send + Sync + 'static,
+        ) -> Result<Arc<AtomicBool>, RkllmError> {
             self.ensure_alive()?;
 
+            let rkllm_input = self.prepare_input(rkllm_input, rkllm_infer_params.as_ref())?;
             let run_args = RunArguments::new(rkllm_input, rkllm_infer_params)?;
-            let mut instance_data = Arc::new(InstanceData::new(user_data, Some(run_args)));
-            let (input_ptr, infer_param_ptr) = {
-                let data = Arc::get_mut(&mut instance_data).ok_or_else(|| {
-                    io::Error::new(
-                        io::ErrorKind::Other,
-                        "failed to get mutable callback context",
-                    )
-                })?;
-                data.run_args_ptrs()?
-            };
+            let mut data = InstanceData::new(self, user_data, run_args, false);
+            let (input_ptr, infer_param_ptr) = data.run_args_ptrs();
+            let instance_data = Arc::new(data);
             let mut callback_context = CallbackContextGuard::new(&instance_data);
 
             let ret = unsafe {
@@ -657,37 +958,80 @@ pub mod prelude {
             if ret == 0 || instance_data.finished.load(Ordering::Acquire) {
                 callback_context.disarm();
             }
+            if ret != 0 {
+                instance_data.end_run();
+            }
 
-            status_to_result("rkllm_run_async", ret)
+            status_to_result("rkllm_run_async", ret)?;
+            Ok(Arc::clone(&instance_data.aborted))
         }
 
-        pub fn load_prompt_cache(&self, cache_path: &str) -> Result<(), BoxError> {
+        pub fn load_prompt_cache(&self, cache_path: &str) -> Result<(), RkllmError> {
             self.ensure_alive()?;
             let prompt_cache_path = CString::new(cache_path)?;
             let ret =
                 unsafe { super::rkllm_load_prompt_cache(self.handle, prompt_cache_path.as_ptr()) };
-            status_to_result("rkllm_load_prompt_cache", ret)
+            status_to_result("rkllm_load_prompt_cache", ret)?;
+            if let Ok(mut prompt_cache) = self.prompt_cache.lock() {
+                *prompt_cache = Some(cache_path.to_owned());
+            }
+            Ok(())
         }
 
-        pub fn release_prompt_cache(&self) -> Result<(), BoxError> {
+        pub fn release_prompt_cache(&self) -> Result<(), RkllmError> {
             self.ensure_alive()?;
             let ret = unsafe { super::rkllm_release_prompt_cache(self.handle) };
-            status_to_result("rkllm_release_prompt_cache", ret)
+            status_to_result("rkllm_release_prompt_cache", ret)?;
+            if let Ok(mut prompt_cache) = self.prompt_cache.lock() {
+                *prompt_cache = None;
+            }
+            Ok(())
         }
 
-        pub fn abort(&self) -> Result<(), BoxError> {
+        pub fn abort(&self) -> Result<(), RkllmError> {
             self.ensure_alive()?;
+            if let Some(aborted) = self.current_run() {
+                aborted.store(true, Ordering::Release);
+            }
             let ret = unsafe { super::rkllm_abort(self.handle) };
             status_to_result("rkllm_abort", ret)
         }
 
-        pub fn is_running(&self) -> Result<(), BoxError> {
-            self.ensure_alive()?;
-            let ret = unsafe { super::rkllm_is_running(self.handle) };
-            status_to_result("rkllm_is_running", ret)
+        pub fn is_running(&self) -> bool {
+            if self.ensure_alive().is_err() {
+                return false;
+            }
+            unsafe { super::rkllm_is_running(self.handle) == 0 }
+        }
+
+        pub fn config(&self) -> &LLMConfig {
+            &self.config
+        }
+
+        pub fn model_path(&self) -> Option<&str> {
+            self.config.model_path.as_deref()
+        }
+
+        pub fn lora_adapters(&self) -> Vec<RKLLMLoraAdapter> {
+            self.lora_adapters
+                .lock()
+                .map(|adapters| adapters.clone())
+                .unwrap_or_default()
+        }
+
+        pub fn prompt_cache(&self) -> Option<String> {
+            self.prompt_cache.lock().ok()?.clone()
+        }
+
+        pub fn is_cross_attn_active(&self) -> bool {
+            self.cross_attn_active.load(Ordering::Acquire)
         }
 
-        pub fn load_lora(&self, lora_cfg: &RKLLMLoraAdapter) -> Result<(), BoxError> {
+        pub fn last_perf(&self) -> Option<RKLLMPerfStatData> {
+            *self.last_perf.lock().ok()?
+        }
+
+        pub fn load_lora(&self, lora_cfg: &RKLLMLoraAdapter) -> Result<(), RkllmError> {
             self.ensure_alive()?;
             let lora_adapter_name = CString::new(lora_cfg.lora_adapter_name.clone())?;
             let lora_adapter_path = CString::new(lora_cfg.lora_adapter_path.clone())?;
@@ -697,7 +1041,12 @@ pub mod prelude {
                 scale: lora_cfg.scale,
             };
             let ret = unsafe { super::rkllm_load_lora(self.handle, &mut param) };
-            status_to_result("rkllm_load_lora", ret)
+            status_to_result("rkllm_load_lora", ret)?;
+            if let Ok(mut adapters) = self.lora_adapters.lock() {
+                adapters.retain(|adapter| adapter.lora_adapter_name != lora_cfg.lora_adapter_name);
+                adapters.push(lora_cfg.clone());
+            }
+            Ok(())
         }
 
         pub fn clear_kv_cache(
@@ -705,14 +1054,13 @@ pub mod prelude {
             keep_system_prompt: bool,
             start_pos: Option<&[i32]>,
             end_pos: Option<&[i32]>,
-        ) -> Result<(), BoxError> {
+        ) -> Result<(), RkllmError> {
             self.ensure_alive()?;
             if let (Some(start), Some(end)) = (start_pos, end_pos) {
                 if start.len() != end.len() {
-                    return Err(Box::new(io::Error::new(
-                        io::ErrorKind::InvalidInput,
+                    return Err(RkllmError::invalid_argument(
                         "start_pos and end_pos length mismatch",
-                    )));
+                    ));
                 }
             }
 
@@ -729,19 +1077,26 @@ pub mod prelude {
             status_to_result("rkllm_clear_kv_cache", ret)
         }
 
-        pub fn get_kv_cache_size(&self, cache_sizes: &mut [i32]) -> Result<(), BoxError> {
+        pub fn get_kv_cache_size(&self, cache_sizes: &mut [i32]) -> Result<(), RkllmError> {
             self.ensure_alive()?;
             let ret =
                 unsafe { super::rkllm_get_kv_cache_size(self.handle, cache_sizes.as_mut_ptr()) };
             status_to_result("rkllm_get_kv_cache_size", ret)
         }
 
+        fn kv_cache_is_empty(&self) -> Result<bool, RkllmError> {
+            let n_batch = usize::from(self.config().extend_param.n_batch.max(1));
+            let mut cache_sizes = vec![0; n_batch];
+            self.get_kv_cache_size(&mut cache_sizes)?;
+            Ok(cache_sizes.iter().all(|&size| size == 0))
+        }
+
         pub fn set_chat_template(
             &self,
             system_prompt: &str,
             prompt_prefix: &str,
             prompt_postfix: &str,
-        ) -> Result<(), BoxError> {
+        ) -> Result<(), RkllmError> {
             self.ensure_alive()?;
             let system_prompt = CString::new(system_prompt)?;
             let prompt_prefix = CString::new(prompt_prefix)?;
@@ -754,7 +1109,57 @@ pub mod prelude {
                     prompt_postfix.as_ptr(),
                 )
             };
-            status_to_result("rkllm_set_chat_template", ret)
+            status_to_result("rkllm_set_chat_template", ret)?;
+            if let Ok(mut chat_renderer) = self.chat_renderer.lock() {
+                *chat_renderer = None;
+            }
+            if let Ok(mut runtime_chat_template) = self.runtime_chat_template.lock() {
+                *runtime_chat_template = Some(RuntimeChatTemplate {
+                    system_prompt: system_prompt.into_string().unwrap_or_default(),
+                    prompt_prefix: prompt_prefix.into_string().unwrap_or_default(),
+                    prompt_postfix: prompt_postfix.into_string().unwrap_or_default(),
+                });
+            }
+            Ok(())
+        }
+
+        // Renders prompts on the Rust side; the runtime template is emptied so it does not wrap
+        // the rendered text a second time.
+        pub fn set_chat_renderer(
+            &self,
+            renderer: impl ChatRenderer + 'static,
+        ) -> Result<(), RkllmError> {
+            self.set_chat_template("", "", "")?;
+            if let Ok(mut chat_renderer) = self.chat_renderer.lock() {
+                *chat_renderer = Some(Arc::new(renderer));
+            }
+            Ok(())
+        }
+
+        pub fn clear_chat_renderer(&self) {
+            if let Ok(mut chat_renderer) = self.chat_renderer.lock() {
+                *chat_renderer = None;
+            }
+        }
+
+        pub fn chat_renderer(&self) -> Option<Arc<dyn ChatRenderer>> {
+            self.chat_renderer.lock().ok()?.clone()
+        }
+
+        pub fn runtime_chat_template(&self) -> Option<RuntimeChatTemplate> {
+            self.runtime_chat_template.lock().ok()?.clone()
+        }
+
+        pub fn render_messages(
+            &self,
+            messages: &[ChatMessage],
+            add_generation_prompt: bool,
+            enable_thinking: bool,
+        ) -> Result<String, RkllmError> {
+            let renderer = self.chat_renderer().ok_or(RkllmError::UnsupportedInput(
+                "no chat renderer is set on the handle",
+            ))?;
+            renderer.render(messages, add_generation_prompt, enable_thinking)
         }
 
         pub fn set_function_tools<T: Serialize>(
@@ -762,16 +1167,13 @@ pub mod prelude {
             system_prompt: &str,
             tools: &T,
             tool_response_str: &str,
-        ) -> Result<(), BoxError> {
+        ) -> Result<(), RkllmError> {
             self.ensure_alive()?;
             let system_prompt = CString::new(system_prompt)?;
-            let tools_json = serde_json::to_string(tools).map_err(|err| {
-                Box::new(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    format!("failed to serialize tools: {}", err),
-                )) as BoxError
-            })?;
-            let tools_json = CString::new(tools_json)?;
+            // Serialized directly so fields reach the runtime in declaration order; the `Value`
+            // kept for validation would sort them.
+            let tools_json = CString::new(serde_json::to_string(tools)?)?;
+            let tools = serde_json::to_value(tools)?;
             let tool_response = CString::new(tool_response_str)?;
 
             let ret = unsafe {
@@ -782,28 +1184,50 @@ pub mod prelude {
                     tool_response.as_ptr(),
                 )
             };
-            status_to_result("rkllm_set_function_tools", ret)
+            status_to_result("rkllm_set_function_tools", ret)?;
+            if let Ok(mut function_tools) = self.function_tools.lock() {
+                *function_tools = Some(tools);
+            }
+            Ok(())
+        }
+
+        // The tools list last passed to `set_function_tools`, as JSON.
+        pub fn function_tools(&self) -> Option<serde_json::Value> {
+            self.function_tools.lock().ok()?.clone()
+        }
+
+        // Token id to bytes mapping used by constrained completions.
+        pub fn set_vocabulary(&self, vocabulary: impl Vocabulary + 'static) {
+            if let Ok(mut current) = self.vocabulary.lock() {
+                *current = Some(Arc::new(vocabulary));
+            }
+        }
+
+        pub fn vocabulary(&self) -> Option<Arc<dyn Vocabulary>> {
+            self.vocabulary.lock().ok()?.clone()
         }
 
         pub fn with_cross_attn<F, R>(
             &self,
             cross_attn_params: &CrossAttnParam,
             func: F,
-        ) -> Result<R, BoxError>
+        ) -> Result<R, RkllmError>
         where
             F: FnOnce(&LLMHandle) -> R,
         {
             self.ensure_alive()?;
 
-            struct ResetGuard {
+            struct ResetGuard<'a> {
                 handle: super::LLMHandle,
+                active: &'a AtomicBool,
             }
 
-            impl Drop for ResetGuard {
+            impl Drop for ResetGuard<'_> {
                 fn drop(&mut self) {
                     unsafe {
                         super::rkllm_set_cross_attn_params(self.handle, std::ptr::null_mut());
                     }
+                    self.active.store(false, Ordering::Release);
                 }
             }
 
@@ -818,8 +1242,10 @@ pub mod prelude {
             let ret = unsafe { super::rkllm_set_cross_attn_params(self.handle, &mut c_params) };
             status_to_result("rkllm_set_cross_attn_params", ret)?;
 
+            self.cross_attn_active.store(true, Ordering::Release);
             let _guard = ResetGuard {
                 handle: self.handle,
+                active: &self.cross_attn_active,
             };
             Ok(func(self))
         }
@@ -854,19 +1280,56 @@ pub mod prelude {
             _ => LLMCallState::Error,
         };
 
-        let result = if result.is_null() {
-            None
-        } else {
-            Some(unsafe { RKLLMResult::from_raw(&*result) })
-        };
+        let terminal = matches!(state, LLMCallState::Finish | LLMCallState::Error);
+        if state == LLMCallState::Finish && !result.is_null() {
+            if let Ok(mut last_perf) = instance_data.last_perf.lock() {
+                *last_perf = Some(unsafe { (*result).perf }.into());
+            }
+        }
 
-        if let Ok(mut handler) = instance_data.callback_handler.lock() {
-            handler.handle(result, state);
+        // Unwinding out of an `extern "C"` function aborts the process, so user panics are
+        // parked here and re-raised by `LLMHandle::run` on the caller's thread.
+        if !instance_data.has_panicked() {
+            let dispatch = panic::catch_unwind(AssertUnwindSafe(|| {
+                let result = if result.is_null() {
+                    None
+                } else {
+                    Some(unsafe { RKLLMResult::from_raw(&*result) })
+                };
+
+                if let Ok(mut handler) = instance_data.callback_handler.lock() {
+                    handler.handle(result, state);
+                }
+            }));
+
+            if let Err(payload) = dispatch {
+                instance_data.store_panic(payload);
+                if !terminal {
+                    let _ = unsafe { super::rkllm_abort(instance_data.handle) };
+                }
+            }
+        }
+
+        // Nobody re-raises the panic of an async run, so the handler is told the run failed
+        // instead; `Generation` and `TokenStream` report it as `RkllmError::Callback`.
+        let failed = terminal && !instance_data.reraise_panic && instance_data.has_panicked();
+        if failed {
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut handler = instance_data
+                    .callback_handler
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                handler.handle(None, LLMCallState::Error);
+            }));
         }
 
         // The runtime owns one raw Arc pointer across callbacks; once we hit a terminal state
         // we stop restoring that raw pointer so Rust can drop callback state automatically.
-        if matches!(state, LLMCallState::Finish | LLMCallState::Error) {
+        if terminal {
+            if state == LLMCallState::Error || failed {
+                instance_data.errored.store(true, Ordering::Release);
+            }
+            instance_data.end_run();
             instance_data.finished.store(true, Ordering::Release);
         } else {
             let _ = Arc::into_raw(instance_data);
@@ -875,23 +1338,26 @@ pub mod prelude {
         0
     }
 
-    pub fn init(config: LLMConfig) -> Result<LLMHandle, BoxError> {
+    pub fn init(config: LLMConfig) -> Result<LLMHandle, RkllmError> {
         let (mut raw_param, owned_strings) = raw_param_from_config(&config)?;
         unsafe { rkllm_init_raw_with_strings(&mut raw_param as *mut _, owned_strings) }
     }
 
-    pub fn init_with_model_path(model_path: impl Into<String>) -> Result<LLMHandle, BoxError> {
+    pub fn init_with_model_path(model_path: impl Into<String>) -> Result<LLMHandle, RkllmError> {
         init(LLMConfig::with_model_path(model_path))
     }
 
     #[deprecated(since = "0.1.15", note = "Use init_with_model_path(...) instead.")]
     pub fn rkllm_init_with_model_path(
         model_path: impl Into<String>,
-    ) -> Result<LLMHandle, BoxError> {
+    ) -> Result<LLMHandle, RkllmError> {
         init_with_model_path(model_path)
     }
 
-    pub unsafe fn init_raw(param: &mut super::RKLLMParam) -> Result<LLMHandle, BoxError> {
+    /// # Safety
+    ///
+    /// Every string pointer in `param` must stay valid for the lifetime of the returned handle.
+    pub unsafe fn init_raw(param: &mut super::RKLLMParam) -> Result<LLMHandle, RkllmError> {
         unsafe { rkllm_init_raw_with_strings(param as *mut _, InitParamStrings::default()) }
     }
 
@@ -899,31 +1365,67 @@ pub mod prelude {
         since = "0.1.15",
         note = "Use init_raw(...) for explicit low-level usage."
     )]
-    pub unsafe fn rkllm_init_raw(param: &mut super::RKLLMParam) -> Result<LLMHandle, BoxError> {
+    /// # Safety
+    ///
+    /// See [`init_raw`].
+    pub unsafe fn rkllm_init_raw(param: &mut super::RKLLMParam) -> Result<LLMHandle, RkllmError> {
         unsafe { init_raw(param) }
     }
 
     unsafe fn rkllm_init_raw_with_strings(
         param: *mut super::RKLLMParam,
         owned_param_strings: InitParamStrings,
-    ) -> Result<LLMHandle, BoxError> {
+    ) -> Result<LLMHandle, RkllmError> {
         let mut handle = std::ptr::null_mut();
         let callback: Option<
             unsafe extern "C" fn(*mut super::RKLLMResult, *mut c_void, super::LLMCallState) -> i32,
         > = Some(callback_passtrough);
 
+        let config = unsafe { config_from_raw(&*param) };
         let ret = unsafe { super::rkllm_init(&mut handle, param, callback) };
         if ret == 0 {
-            Ok(LLMHandle {
+            Ok(LLMHandle::from_parts(handle, config, owned_param_strings))
+        } else {
+            Err(RkllmError::Init { code: ret })
+        }
+    }
+
+    impl LLMHandle {
+        fn from_parts(
+            handle: super::LLMHandle,
+            config: LLMConfig,
+            owned_param_strings: InitParamStrings,
+        ) -> Self {
+            LLMHandle {
                 handle,
                 is_destroyed: AtomicBool::new(false),
+                current_run: Arc::new(Mutex::new(None)),
+                config,
+                lora_adapters: Mutex::new(Vec::new()),
+                prompt_cache: Mutex::new(None),
+                chat_renderer: Mutex::new(None),
+                runtime_chat_template: Mutex::new(None),
+                function_tools: Mutex::new(None),
+                vocabulary: Mutex::new(None),
+                cross_attn_active: AtomicBool::new(false),
+                last_perf: Arc::new(Mutex::new(None)),
                 _owned_param_strings: owned_param_strings,
-            })
-        } else {
-            Err(Box::new(io::Error::new(
-                io::ErrorKind::Other,
-                format!("rkllm_init returned non-zero: {}", ret),
-            )))
+            }
+        }
+
+        // A handle with no runtime behind it, for unit tests of the Rust-side state. Every
+        // runtime call fails with `RkllmError::HandleDestroyed`.
+        #[cfg(test)]
+        pub(crate) fn detached(
+            config: LLMConfig,
+            chat_renderer: Option<Arc<dyn ChatRenderer>>,
+        ) -> Self {
+            let handle =
+                Self::from_parts(std::ptr::null_mut(), config, InitParamStrings::default());
+            if let Ok(mut renderer) = handle.chat_renderer.lock() {
+                *renderer = chat_renderer;
+            }
+            handle
         }
     }
 
@@ -942,6 +1444,38 @@ pub mod prelude {
             }
         }
 
+        pub fn tokens(token_ids: impl Into<Vec<i32>>) -> Self {
+            Self {
+                input_type: RKLLMInputType::Token(token_ids.into()),
+                enable_thinking: false,
+                role: RKLLMInputRole::User,
+            }
+        }
+
+        pub fn embed(embed_input: EmbedInput) -> Self {
+            Self {
+                input_type: RKLLMInputType::Embed(embed_input),
+                enable_thinking: false,
+                role: RKLLMInputRole::User,
+            }
+        }
+
+        pub fn multimodal(multimodal_input: MultimodalInput) -> Self {
+            Self {
+                input_type: RKLLMInputType::Multimodal(multimodal_input),
+                enable_thinking: false,
+                role: RKLLMInputRole::User,
+            }
+        }
+
+        pub fn messages(messages: impl Into<Vec<ChatMessage>>) -> Self {
+            Self {
+                input_type: RKLLMInputType::Messages(messages.into()),
+                enable_thinking: false,
+                role: RKLLMInputRole::User,
+            }
+        }
+
         #[allow(non_snake_case)]
         #[deprecated(since = "0.1.14", note = "Use RKLLMInput::prompt(...) instead.")]
         pub fn Prompt(prompt: impl Into<String>) -> Self {
@@ -949,15 +1483,272 @@ pub mod prelude {
         }
     }
 
+    #[derive(Debug, Clone)]
+    pub struct EmbedInput {
+        embed: Vec<f32>,
+        n_tokens: usize,
+        n_embed: usize,
+    }
+
+    impl EmbedInput {
+        pub fn new(embed: impl Into<Vec<f32>>, n_embed: usize) -> Result<Self, RkllmError> {
+            let embed = embed.into();
+            if n_embed == 0 || embed.len() % n_embed != 0 {
+                return Err(RkllmError::invalid_argument(format!(
+                    "embedding length {} is not a multiple of n_embed {}",
+                    embed.len(),
+                    n_embed
+                )));
+            }
+            let n_tokens = embed.len() / n_embed;
+            Self::with_shape(embed, n_tokens, n_embed)
+        }
+
+        pub fn with_shape(
+            embed: impl Into<Vec<f32>>,
+            n_tokens: usize,
+            n_embed: usize,
+        ) -> Result<Self, RkllmError> {
+            let embed = embed.into();
+            if n_tokens == 0 || n_embed == 0 {
+                return Err(RkllmError::invalid_argument(
+                    "embedding input must contain at least one token",
+                ));
+            }
+            if n_tokens.checked_mul(n_embed) != Some(embed.len()) {
+                return Err(RkllmError::invalid_argument(format!(
+                    "embedding length {} does not match n_tokens {} * n_embed {}",
+                    embed.len(),
+                    n_tokens,
+                    n_embed
+                )));
+            }
+            Ok(Self {
+                embed,
+                n_tokens,
+                n_embed,
+            })
+        }
+
+        pub fn for_hidden_layer(
+            embed: impl Into<Vec<f32>>,
+            hidden_layer: &RKLLMResultLastHiddenLayerData<'_>,
+        ) -> Result<Self, RkllmError> {
+            let n_embed = usize::try_from(hidden_layer.embd_size).map_err(|_| {
+                RkllmError::invalid_argument(format!(
+                    "invalid hidden layer embd_size {}",
+                    hidden_layer.embd_size
+                ))
+            })?;
+            Self::new(embed, n_embed)
+        }
+
+        pub fn embed(&self) -> &[f32] {
+            &self.embed
+        }
+
+        pub fn n_tokens(&self) -> usize {
+            self.n_tokens
+        }
+
+        pub fn n_embed(&self) -> usize {
+            self.n_embed
+        }
+    }
+
+    impl TryFrom<RKLLMResultLastHiddenLayerData<'_>> for EmbedInput {
+        type Error = RkllmError;
+
+        fn try_from(value: RKLLMResultLastHiddenLayerData<'_>) -> Result<Self, Self::Error> {
+            Self::for_hidden_layer(value.hidden_states.to_vec(), &value)
+        }
+    }
+
+    pub const DEFAULT_IMAGE_PLACEHOLDER: &str = "<image>";
+
+    #[derive(Debug, Clone)]
+    pub struct MultimodalInput {
+        prompt: String,
+        placeholder: Option<String>,
+        image_embed: Vec<f32>,
+        n_image: usize,
+        n_image_tokens: usize,
+        embed_len: usize,
+        image_width: usize,
+        image_height: usize,
+    }
+
+    impl MultimodalInput {
+        pub fn new(prompt: impl Into<String>, n_image_tokens: usize, embed_len: usize) -> Self {
+            Self {
+                prompt: prompt.into(),
+                placeholder: None,
+                image_embed: Vec::new(),
+                n_image: 0,
+                n_image_tokens,
+                embed_len,
+                image_width: 0,
+                image_height: 0,
+            }
+        }
+
+        pub fn with_images(
+            prompt: impl Into<String>,
+            image_embed: impl Into<Vec<f32>>,
+            n_image: usize,
+            n_image_tokens: usize,
+            embed_len: usize,
+        ) -> Result<Self, RkllmError> {
+            let image_embed = image_embed.into();
+            let expected = n_image
+                .checked_mul(n_image_tokens)
+                .and_then(|len| len.checked_mul(embed_len));
+            if n_image_tokens == 0 || embed_len == 0 || expected != Some(image_embed.len()) {
+                return Err(RkllmError::invalid_argument(format!(
+                    "image embedding length {} does not match n_image {} * n_image_tokens {} * embed_len {}",
+                    image_embed.len(),
+                    n_image,
+                    n_image_tokens,
+                    embed_len
+                )));
+            }
+            let mut input = Self::new(prompt, n_image_tokens, embed_len);
+            input.image_embed = image_embed;
+            input.n_image = n_image;
+            Ok(input)
+        }
+
+        pub fn with_image(mut self, image_embed: &[f32]) -> Result<Self, RkllmError> {
+            let expected = self.n_image_tokens.checked_mul(self.embed_len);
+            if self.n_image_tokens == 0 || expected != Some(image_embed.len()) {
+                return Err(RkllmError::invalid_argument(format!(
+                    "image embedding length {} does not match n_image_tokens {} * embed_len {}",
+                    image_embed.len(),
+                    self.n_image_tokens,
+                    self.embed_len
+                )));
+            }
+            self.image_embed.extend_from_slice(image_embed);
+            self.n_image += 1;
+            Ok(self)
+        }
+
+        pub fn with_image_size(mut self, width: usize, height: usize) -> Self {
+            self.image_width = width;
+            self.image_height = height;
+            self
+        }
+
+        // Defaults to the handle's `img_content`, or `DEFAULT_IMAGE_PLACEHOLDER` when the config
+        // has none. An empty placeholder passes the prompt through unchecked.
+        pub fn with_placeholder(mut self, placeholder: impl Into<String>) -> Self {
+            self.placeholder = Some(placeholder.into());
+            self
+        }
+
+        pub fn placeholder(&self) -> Option<&str> {
+            self.placeholder.as_deref()
+        }
+
+        pub fn prompt(&self) -> &str {
+            &self.prompt
+        }
+
+        pub fn image_embed(&self) -> &[f32] {
+            &self.image_embed
+        }
+
+        pub fn n_image(&self) -> usize {
+            self.n_image
+        }
+
+        pub fn n_image_tokens(&self) -> usize {
+            self.n_image_tokens
+        }
+
+        pub fn embed_len(&self) -> usize {
+            self.embed_len
+        }
+
+        // The runtime puts the image tokens at each placeholder, so a prompt without one would
+        // silently drop the images and a prompt with a different count would misplace them.
+        fn prompt_with_placeholders(&self) -> Result<String, RkllmError> {
+            let placeholder = self
+                .placeholder
+                .as_deref()
+                .unwrap_or(DEFAULT_IMAGE_PLACEHOLDER);
+            if placeholder.is_empty() {
+                return Ok(self.prompt.clone());
+            }
+            match self.prompt.matches(placeholder).count() {
+                0 => Ok(format!(
+                    "{}{}",
+                    placeholder.repeat(self.n_image),
+                    self.prompt
+                )),
+                count if count == self.n_image => Ok(self.prompt.clone()),
+                count => Err(RkllmError::invalid_argument(format!(
+                    "prompt has {} {} placeholders for {} images",
+                    count, placeholder, self.n_image
+                ))),
+            }
+        }
+    }
+
     pub enum RKLLMInputType {
         Prompt(String),
-        Token(String),
-        Embed(String),
-        Multimodal(String),
+        Token(Vec<i32>),
+        Embed(EmbedInput),
+        Multimodal(MultimodalInput),
+        Messages(Vec<ChatMessage>),
     }
 
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(from = "String", into = "String")]
     pub enum RKLLMInputRole {
         User,
         Tool,
+        System,
+        Assistant,
+        Custom(String),
+    }
+
+    impl RKLLMInputRole {
+        pub fn as_str(&self) -> &str {
+            match self {
+                RKLLMInputRole::User => "user",
+                RKLLMInputRole::Tool => "tool",
+                RKLLMInputRole::System => "system",
+                RKLLMInputRole::Assistant => "assistant",
+                RKLLMInputRole::Custom(role) => role,
+            }
+        }
+
+        pub fn from_name(name: &str) -> Self {
+            match name {
+                "user" => RKLLMInputRole::User,
+                "tool" => RKLLMInputRole::Tool,
+                "system" => RKLLMInputRole::System,
+                "assistant" => RKLLMInputRole::Assistant,
+                role => RKLLMInputRole::Custom(role.to_owned()),
+            }
+        }
+
+        // The runtime only understands "user" and "tool" as string roles.
+        pub fn is_native(&self) -> bool {
+            matches!(self, RKLLMInputRole::User | RKLLMInputRole::Tool)
+        }
+    }
+
+    impl From<String> for RKLLMInputRole {
+        fn from(name: String) -> Self {
+            RKLLMInputRole::from_name(&name)
+        }
+    }
+
+    impl From<RKLLMInputRole> for String {
+        fn from(role: RKLLMInputRole) -> Self {
+            role.as_str().to_owned()
+        }
     }
 }
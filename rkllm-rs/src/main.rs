@@ -1,5 +1,4 @@
-use clap::builder::PossibleValue;
-use clap::{Arg, ArgAction, Command, ValueEnum};
+use clap::{Arg, ArgAction, Command};
 use rkllm_rs::prelude::*;
 use std::io;
 use std::io::Write;
@@ -9,6 +8,8 @@ use autotokenizer::AutoTokenizer;
 #[cfg(feature = "online_config")]
 use autotokenizer::DefaultPromptMessage;
 
+const SYSTEM_PROMPT: &str = "You are a smart speaker, please help users with questions.";
+
 struct UserDataWithCallBack {
     userdata: String,
 }
@@ -24,53 +25,12 @@ impl RkllmCallbackHandler for UserDataWithCallBack {
                 println!("\n{}", self.userdata);
             }
             LLMCallState::Error => {
-                print!("\\run error\n");
+                println!("\\run error");
             }
             LLMCallState::GetLastHiddenLayer => {
-                print!("GetLastHiddenLayer\n");
-            }
-        }
-    }
-}
-
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
-enum ModelType {
-    Normal,
-    DeepSeek,
-}
-
-impl ValueEnum for ModelType {
-    fn value_variants<'a>() -> &'a [Self] {
-        &[ModelType::Normal, ModelType::DeepSeek]
-    }
-
-    fn to_possible_value(&self) -> Option<PossibleValue> {
-        Some(match self {
-            ModelType::Normal => PossibleValue::new("normal").help("Normal model"),
-            ModelType::DeepSeek => PossibleValue::new("deepseek").help("DeepSeek"),
-        })
-    }
-}
-
-impl std::fmt::Display for ModelType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.to_possible_value()
-            .expect("no values are skipped")
-            .get_name()
-            .fmt(f)
-    }
-}
-
-impl std::str::FromStr for ModelType {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        for variant in Self::value_variants() {
-            if variant.to_possible_value().unwrap().matches(s, false) {
-                return Ok(*variant);
+                println!("GetLastHiddenLayer");
             }
         }
-        Err(format!("invalid variant: {s}"))
     }
 }
 
@@ -89,7 +49,7 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .arg(
             Arg::new("model_type")
                 .long("model_type")
-                .help("some module have special prefix in prompt, use this to fix")
+                .help("Chat template name (deepseek-r1, qwen2, qwen2.5, qwen3, llama3, gemma2, gemma3, phi3, minicpm, chatglm, internlm, chatml) or normal for raw prompts; detected from the model file name when omitted (with the local_config feature, a local model directory, tokenizer_config.json or .jinja file)")
                 .action(ArgAction::Set)
                 .num_args(1),
         )
@@ -175,27 +135,36 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let mut config = LLMConfig::default();
     // Prompt cache path can be applied at init and later loaded explicitly.
     let mut cache_path = None;
-    let mut modeltype = ModelType::Normal;
+    let model_type = matches.get_one::<String>("model_type");
+    let mut builtin_template = None;
     #[cfg(feature = "online_config")]
     let mut atoken = None;
+    #[cfg(feature = "local_config")]
+    let mut local_template = None;
 
-    if let Some(value) = matches.get_one::<String>("model_type") {
-        #[cfg(not(feature = "online_config"))]
+    if let Some(value) = model_type {
+        #[cfg(feature = "local_config")]
         {
-            if value == "deepseek" {
-                modeltype = ModelType::DeepSeek;
+            if std::path::Path::new(value).exists() {
+                local_template = Some(JinjaTemplate::from_path(value)?);
             }
         }
+        builtin_template = ChatTemplate::by_name(value).or_else(|| ChatTemplate::detect(value));
         #[cfg(feature = "online_config")]
         {
             if let Ok(_atoken) = AutoTokenizer::from_pretrained(value.clone(), None) {
                 atoken = Some(_atoken);
+                // The fetched template already renders the prompt.
+                builtin_template = None;
             };
         }
     }
 
     if let Some(value) = matches.get_one::<String>("model") {
         config.model_path = Some(value.clone());
+        if model_type.is_none() {
+            builtin_template = ChatTemplate::detect(value);
+        }
     }
     if let Some(value) = matches.get_one::<i32>("max_context_len") {
         config.max_context_len = *value;
@@ -229,18 +198,25 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     }
 
     let llm_handle = init(config)?;
+    if let Some(template) = builtin_template {
+        llm_handle.apply_chat_template(template, None)?;
+    }
+    #[cfg(feature = "local_config")]
+    let use_local_template = match local_template {
+        Some(template) => {
+            llm_handle.set_chat_renderer(template)?;
+            true
+        }
+        None => false,
+    };
 
     let rkllm_infer_params = RKLLMInferParam {
         mode: RKLLMInferMode::InferGenerate,
         lora_params: None,
-        prompt_cache_params: if let Some(cache_path) = cache_path {
-            Some(RKLLMPromptCacheParam {
-                save_prompt_cache: true,
-                prompt_cache_path: cache_path.to_owned(),
-            })
-        } else {
-            None
-        },
+        prompt_cache_params: cache_path.map(|cache_path| RKLLMPromptCacheParam {
+            save_prompt_cache: true,
+            prompt_cache_path: cache_path.to_owned(),
+        }),
         keep_history: KeepHistory::NoKeepHistory,
     };
     if let Some(cache_path) = cache_path {
@@ -258,21 +234,11 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             println!("Exit!");
             break;
         } else {
-            // Change prompt for different type model
-            input = match modeltype {
-                ModelType::Normal => input,
-                ModelType::DeepSeek => {
-                    format!("<｜begin▁of▁sentence｜><｜User｜>{}<｜Assistant｜>", input)
-                }
-            };
             #[cfg(feature = "online_config")]
             {
                 // 定義對話上下文
                 let ctx = vec![
-                    DefaultPromptMessage::new(
-                        "system",
-                        "You are a smart speaker, please help users with questions.",
-                    ),
+                    DefaultPromptMessage::new("system", SYSTEM_PROMPT),
                     DefaultPromptMessage::new("user", &input),
                 ];
 
@@ -287,10 +253,22 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             // For AutoTokenizer debug
             // println!("{}", input);
 
+            #[cfg(feature = "local_config")]
+            let input_type = if use_local_template {
+                RKLLMInputType::Messages(vec![
+                    ChatMessage::system(SYSTEM_PROMPT),
+                    ChatMessage::user(input),
+                ])
+            } else {
+                RKLLMInputType::Prompt(input)
+            };
+            #[cfg(not(feature = "local_config"))]
+            let input_type = RKLLMInputType::Prompt(input);
+
             print!("\nRobot: \n");
             let _ = llm_handle.run(
                 RKLLMInput {
-                    input_type: RKLLMInputType::Prompt(input),
+                    input_type,
                     enable_thinking: false,
                     role: RKLLMInputRole::User,
                 },
@@ -0,0 +1,372 @@
+use crate::generation::{Generation, TokenStream};
+use crate::prelude::{RkllmError, Token};
+use futures::stream::Stream;
+use futures::{ready, StreamExt};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+pub const DEFAULT_REASONING_OPEN_TAG: &str = "<think>";
+pub const DEFAULT_REASONING_CLOSE_TAG: &str = "</think>";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReasoningChunk {
+    Reasoning(String),
+    Answer(String),
+}
+
+// Splits streamed text into reasoning and answer parts. Text that could be the start of a tag is
+// held back until the next chunk decides it, so tags split across callbacks are still found.
+// `Waiting` callbacks carry no text and never reach the parser.
+#[derive(Debug, Clone)]
+pub struct ReasoningParser {
+    open_tag: String,
+    close_tag: String,
+    in_reasoning: bool,
+    pending: String,
+    // Bytes pushed before `pending`.
+    consumed: usize,
+}
+
+impl Default for ReasoningParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReasoningParser {
+    pub fn new() -> Self {
+        Self::with_tags(DEFAULT_REASONING_OPEN_TAG, DEFAULT_REASONING_CLOSE_TAG)
+    }
+
+    pub fn with_tags(open_tag: impl Into<String>, close_tag: impl Into<String>) -> Self {
+        Self {
+            open_tag: open_tag.into(),
+            close_tag: close_tag.into(),
+            in_reasoning: false,
+            pending: String::new(),
+            consumed: 0,
+        }
+    }
+
+    // For templates whose generation prompt already ends with the open tag (e.g. DeepSeek-R1),
+    // so the model only emits the close tag.
+    pub fn starting_in_reasoning(mut self, in_reasoning: bool) -> Self {
+        self.in_reasoning = in_reasoning;
+        self
+    }
+
+    pub fn in_reasoning(&self) -> bool {
+        self.in_reasoning
+    }
+
+    pub fn push(&mut self, text: &str) -> Vec<ReasoningChunk> {
+        self.push_at(text)
+            .into_iter()
+            .map(|(_, chunk)| chunk)
+            .collect()
+    }
+
+    // Flushes text held back as a possible tag prefix.
+    pub fn finish(&mut self) -> Option<ReasoningChunk> {
+        self.finish_at().map(|(_, chunk)| chunk)
+    }
+
+    // Like `push`, with the offset of every chunk in all text pushed so far.
+    pub(crate) fn push_at(&mut self, text: &str) -> Vec<(usize, ReasoningChunk)> {
+        let mut chunks = Vec::new();
+        self.pending.push_str(text);
+        loop {
+            let tag = if self.in_reasoning {
+                &self.close_tag
+            } else {
+                &self.open_tag
+            };
+            if tag.is_empty() {
+                break;
+            }
+            if let Some(pos) = self.pending.find(tag.as_str()) {
+                let tag_len = tag.len();
+                let text: String = self.pending.drain(..pos).collect();
+                self.pending.drain(..tag_len);
+                self.emit(&mut chunks, text);
+                self.consumed += tag_len;
+                self.in_reasoning = !self.in_reasoning;
+                continue;
+            }
+            let keep = partial_tag_len(&self.pending, tag);
+            let text: String = self.pending.drain(..self.pending.len() - keep).collect();
+            self.emit(&mut chunks, text);
+            return chunks;
+        }
+        let text = std::mem::take(&mut self.pending);
+        self.emit(&mut chunks, text);
+        chunks
+    }
+
+    pub(crate) fn finish_at(&mut self) -> Option<(usize, ReasoningChunk)> {
+        let text = std::mem::take(&mut self.pending);
+        let mut chunks = Vec::new();
+        self.emit(&mut chunks, text);
+        chunks.pop()
+    }
+
+    fn emit(&mut self, chunks: &mut Vec<(usize, ReasoningChunk)>, text: String) {
+        if text.is_empty() {
+            return;
+        }
+        let offset = self.consumed;
+        self.consumed += text.len();
+        chunks.push((
+            offset,
+            if self.in_reasoning {
+                ReasoningChunk::Reasoning(text)
+            } else {
+                ReasoningChunk::Answer(text)
+            },
+        ));
+    }
+}
+
+// Length of the longest suffix of `text` that is a proper prefix of `tag`, i.e. how much of
+// streamed text has to be held back in case the tag completes in the next chunk.
+pub(crate) fn partial_tag_len(text: &str, tag: &str) -> usize {
+    (1..tag.len().min(text.len() + 1))
+        .rev()
+        .find(|&len| {
+            let start = text.len() - len;
+            text.is_char_boundary(start) && tag.as_bytes().starts_with(&text.as_bytes()[start..])
+        })
+        .unwrap_or(0)
+}
+
+pub struct ReasoningTokens<I> {
+    tokens: I,
+    parser: ReasoningParser,
+    ready: VecDeque<ReasoningChunk>,
+    done: bool,
+}
+
+impl<I> ReasoningTokens<I> {
+    pub fn new(tokens: I, parser: ReasoningParser) -> Self {
+        Self {
+            tokens,
+            parser,
+            ready: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    pub fn into_inner(self) -> I {
+        self.tokens
+    }
+}
+
+impl<I> Iterator for ReasoningTokens<I>
+where
+    I: Iterator<Item = Result<Token, RkllmError>>,
+{
+    type Item = Result<ReasoningChunk, RkllmError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(chunk) = self.ready.pop_front() {
+                return Some(Ok(chunk));
+            }
+            if self.done {
+                return None;
+            }
+            match self.tokens.next() {
+                Some(Ok(token)) => self.ready.extend(self.parser.push(&token.text)),
+                Some(Err(err)) => return Some(Err(err)),
+                None => {
+                    self.done = true;
+                    self.ready.extend(self.parser.finish());
+                }
+            }
+        }
+    }
+}
+
+impl<I> Stream for ReasoningTokens<I>
+where
+    I: Stream<Item = Result<Token, RkllmError>> + Unpin,
+{
+    type Item = Result<ReasoningChunk, RkllmError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(chunk) = this.ready.pop_front() {
+                return Poll::Ready(Some(Ok(chunk)));
+            }
+            if this.done {
+                return Poll::Ready(None);
+            }
+            match ready!(this.tokens.poll_next_unpin(cx)) {
+                Some(Ok(token)) => this.ready.extend(this.parser.push(&token.text)),
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => {
+                    this.done = true;
+                    this.ready.extend(this.parser.finish());
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Generation<'a> {
+    pub fn split_reasoning(self, parser: ReasoningParser) -> ReasoningTokens<Generation<'a>> {
+        ReasoningTokens::new(self, parser)
+    }
+}
+
+impl<'a> TokenStream<'a> {
+    pub fn split_reasoning(self, parser: ReasoningParser) -> ReasoningTokens<TokenStream<'a>> {
+        ReasoningTokens::new(self, parser)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds `chunks` one by one and merges adjacent chunks of the same kind.
+    fn parse(mut parser: ReasoningParser, chunks: &[&str]) -> Vec<ReasoningChunk> {
+        let mut merged: Vec<ReasoningChunk> = Vec::new();
+        let mut parsed: Vec<_> = chunks.iter().flat_map(|chunk| parser.push(chunk)).collect();
+        parsed.extend(parser.finish());
+        for chunk in parsed {
+            match (merged.last_mut(), chunk) {
+                (Some(ReasoningChunk::Reasoning(last)), ReasoningChunk::Reasoning(text))
+                | (Some(ReasoningChunk::Answer(last)), ReasoningChunk::Answer(text)) => {
+                    last.push_str(&text)
+                }
+                (_, chunk) => merged.push(chunk),
+            }
+        }
+        merged
+    }
+
+    fn reasoning(text: &str) -> ReasoningChunk {
+        ReasoningChunk::Reasoning(text.to_owned())
+    }
+
+    fn answer(text: &str) -> ReasoningChunk {
+        ReasoningChunk::Answer(text.to_owned())
+    }
+
+    #[test]
+    fn splits_whole_tags() {
+        assert_eq!(
+            parse(ReasoningParser::new(), &["<think>hm</think>Hello"]),
+            [reasoning("hm"), answer("Hello")]
+        );
+    }
+
+    #[test]
+    fn finds_tags_split_across_chunks() {
+        assert_eq!(
+            parse(
+                ReasoningParser::new(),
+                &["<th", "ink>h", "m</thi", "nk>Hel", "lo"]
+            ),
+            [reasoning("hm"), answer("Hello")]
+        );
+    }
+
+    #[test]
+    fn finds_tags_fed_byte_by_byte() {
+        let text = "Sure<think>let me see</think>42";
+        let bytes: Vec<String> = text.chars().map(String::from).collect();
+        let chunks: Vec<&str> = bytes.iter().map(String::as_str).collect();
+        assert_eq!(
+            parse(ReasoningParser::new(), &chunks),
+            [answer("Sure"), reasoning("let me see"), answer("42")]
+        );
+    }
+
+    #[test]
+    fn holds_back_a_possible_tag_until_decided() {
+        let mut parser = ReasoningParser::new();
+        assert_eq!(parser.push("a <thi"), [answer("a ")]);
+        assert_eq!(
+            parser.push("s is not a tag"),
+            [answer("<this is not a tag")]
+        );
+        assert_eq!(parser.finish(), None);
+    }
+
+    #[test]
+    fn finish_flushes_an_unfinished_tag() {
+        let mut parser = ReasoningParser::new();
+        assert_eq!(parser.push("Hello <thi"), [answer("Hello ")]);
+        assert_eq!(parser.finish(), Some(answer("<thi")));
+    }
+
+    #[test]
+    fn starts_in_reasoning_for_prefilled_open_tags() {
+        assert_eq!(
+            parse(
+                ReasoningParser::new().starting_in_reasoning(true),
+                &["hm</", "think>Hello"]
+            ),
+            [reasoning("hm"), answer("Hello")]
+        );
+    }
+
+    #[test]
+    fn uses_custom_tags() {
+        assert_eq!(
+            parse(ReasoningParser::with_tags("[[", "]]"), &["a[", "[b]", "]c"]),
+            [answer("a"), reasoning("b"), answer("c")]
+        );
+    }
+
+    #[test]
+    fn partial_tag_len_matches_tag_prefixes() {
+        assert_eq!(partial_tag_len("abc<th", "<think>"), 3);
+        assert_eq!(partial_tag_len("abc", "<think>"), 0);
+        // A full tag is not a partial one.
+        assert_eq!(partial_tag_len("<think>", "<think>"), 0);
+        assert_eq!(partial_tag_len("aé", "éx"), 2);
+    }
+
+    #[test]
+    fn splits_a_token_stream() {
+        let tokens = ["<thi", "nk>hm</think>", "Hello"].map(|text| {
+            Ok(Token {
+                text: text.to_owned(),
+                token_id: 0,
+            })
+        });
+        let chunks: Vec<_> = futures::executor::block_on(
+            ReasoningTokens::new(futures::stream::iter(tokens), ReasoningParser::new())
+                .map(Result::unwrap)
+                .collect(),
+        );
+        assert_eq!(
+            chunks,
+            [
+                ReasoningChunk::Reasoning("hm".to_owned()),
+                ReasoningChunk::Answer("Hello".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_chunk_offsets() {
+        let mut parser = ReasoningParser::new();
+        assert_eq!(
+            parser.push_at("a<think>b</th"),
+            [
+                (0, ReasoningChunk::Answer("a".to_owned())),
+                (8, ReasoningChunk::Reasoning("b".to_owned())),
+            ]
+        );
+        assert_eq!(
+            parser.push_at("ink>c"),
+            [(17, ReasoningChunk::Answer("c".to_owned()))]
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use crate::prelude::{Constraint, RkllmError, TokenIndex};
+use regex_automata::dfa::{dense, Automaton, StartKind};
+use regex_automata::util::primitives::StateID;
+use regex_automata::util::start;
+use regex_automata::Anchored;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Accepts output that matches a regex as a whole. The pattern is compiled once into an anchored
+// byte-level DFA. The tokens allowed in a DFA state are found once per vocabulary by walking
+// the vocabulary's byte trie from that state, and reused whenever the output returns to it.
+#[derive(Debug, Clone)]
+pub struct RegexConstraint {
+    dfa: Arc<dense::DFA<Vec<u32>>>,
+    pattern: String,
+    state: StateID,
+    // Allowed tokens per DFA state, for the `TokenIndex` with id `index_id`.
+    index_id: Option<u64>,
+    allowed: HashMap<StateID, Vec<i32>>,
+}
+
+impl RegexConstraint {
+    pub fn new(pattern: &str) -> Result<Self, RkllmError> {
+        // `\z` makes the DFA report a match only at the end of the output, so a dead state means
+        // no continuation can match.
+        let dfa = dense::Builder::new()
+            .configure(dense::Config::new().start_kind(StartKind::Anchored))
+            .build(&format!(r"(?:{})\z", pattern))
+            .map_err(|err| {
+                RkllmError::invalid_argument(format!("invalid regex {}: {}", pattern, err))
+            })?;
+        let state = dfa
+            .start_state(&start::Config::new().anchored(Anchored::Yes))
+            .map_err(|err| {
+                RkllmError::invalid_argument(format!("invalid regex {}: {}", pattern, err))
+            })?;
+        Ok(Self {
+            dfa: Arc::new(dfa),
+            pattern: pattern.to_owned(),
+            state,
+            index_id: None,
+            allowed: HashMap::new(),
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    fn walk(&self, bytes: &[u8]) -> Option<StateID> {
+        bytes
+            .iter()
+            .try_fold(self.state, |state, &byte| self.step(state, byte))
+    }
+
+    fn step(&self, state: StateID, byte: u8) -> Option<StateID> {
+        let next = self.dfa.next_state(state, byte);
+        (!self.dfa.is_dead_state(next) && !self.dfa.is_quit_state(next)).then_some(next)
+    }
+}
+
+impl Constraint for RegexConstraint {
+    fn allows(&self, bytes: &[u8]) -> bool {
+        !bytes.is_empty() && self.walk(bytes).is_some()
+    }
+
+    fn advance(&mut self, bytes: &[u8]) -> Result<(), RkllmError> {
+        self.state = self.walk(bytes).ok_or_else(|| {
+            RkllmError::invalid_argument(format!(
+                "output does not match the regex {}",
+                self.pattern
+            ))
+        })?;
+        Ok(())
+    }
+
+    fn can_finish(&self) -> bool {
+        self.dfa.is_match_state(self.dfa.next_eoi_state(self.state))
+    }
+
+    fn is_finished(&self) -> bool {
+        self.can_finish() && (0..=u8::MAX).all(|byte| self.step(self.state, byte).is_none())
+    }
+
+    fn allowed_tokens(&mut self, index: &TokenIndex) -> Vec<i32> {
+        if self.index_id != Some(index.id()) {
+            self.index_id = Some(index.id());
+            self.allowed.clear();
+        }
+        if let Some(tokens) = self.allowed.get(&self.state) {
+            return tokens.clone();
+        }
+        let tokens = index.walk(self.state, |&state, byte| self.step(state, byte));
+        self.allowed.insert(self.state, tokens.clone());
+        tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::TokenVocabulary;
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!(RegexConstraint::new("(").is_err());
+        assert_eq!(RegexConstraint::new("a+").unwrap().pattern(), "a+");
+    }
+
+    #[test]
+    fn matches_the_whole_output_from_the_start() {
+        let constraint = RegexConstraint::new("b|yes|no").unwrap();
+        assert!(constraint.allows(b"y"));
+        assert!(constraint.allows(b"no"));
+        assert!(!constraint.allows(b"ab"));
+        assert!(!constraint.allows(b"yes!"));
+        assert!(!constraint.allows(b""));
+    }
+
+    #[test]
+    fn tracks_completion() {
+        let mut phone = RegexConstraint::new(r"\d{3}-\d{4}").unwrap();
+        phone.advance(b"555").unwrap();
+        assert!(!phone.can_finish());
+        assert!(phone.allows(b"-1"));
+        assert!(!phone.allows(b"5"));
+        phone.advance(b"-123").unwrap();
+        assert!(!phone.can_finish());
+        phone.advance(b"4").unwrap();
+        assert!(phone.can_finish());
+        assert!(phone.is_finished());
+
+        let mut word = RegexConstraint::new("[a-z]+").unwrap();
+        word.advance(b"abc").unwrap();
+        assert!(word.can_finish());
+        assert!(!word.is_finished());
+    }
+
+    #[test]
+    fn keeps_its_state_when_a_token_is_rejected() {
+        let mut constraint = RegexConstraint::new("ab").unwrap();
+        constraint.advance(b"a").unwrap();
+        assert!(constraint.advance(b"x").is_err());
+        constraint.advance(b"b").unwrap();
+        assert!(constraint.is_finished());
+    }
+
+    #[test]
+    fn walks_characters_split_across_tokens() {
+        let mut constraint = RegexConstraint::new("é+").unwrap();
+        constraint.advance(&[0xC3]).unwrap();
+        assert!(!constraint.can_finish());
+        assert!(!constraint.allows(&[0xC3]));
+        constraint.advance(&[0xA9]).unwrap();
+        assert!(constraint.can_finish());
+    }
+
+    #[test]
+    fn caches_allowed_tokens_per_state_and_vocabulary() {
+        let vocabulary = TokenVocabulary::new(
+            ["1", "12", "-", "a", "1-"]
+                .iter()
+                .map(|token| token.as_bytes().to_vec())
+                .collect(),
+            Vec::new(),
+        );
+        let index = TokenIndex::new(&vocabulary);
+        let allowed = |constraint: &mut RegexConstraint, index: &TokenIndex| {
+            let mut allowed = constraint.allowed_tokens(index);
+            allowed.sort_unstable();
+            allowed
+        };
+
+        let mut constraint = RegexConstraint::new(r"\d+(-\d+)?").unwrap();
+        assert_eq!(allowed(&mut constraint, &index), [0, 1, 4]);
+        constraint.advance(b"1").unwrap();
+        assert_eq!(allowed(&mut constraint, &index), [0, 1, 2, 4]);
+        // A DFA state already seen is answered from the cache.
+        assert_eq!(constraint.allowed.len(), 2);
+        assert_eq!(allowed(&mut constraint, &index), [0, 1, 2, 4]);
+        assert_eq!(constraint.allowed.len(), 2);
+        constraint.advance(b"-").unwrap();
+        assert_eq!(allowed(&mut constraint, &index), [0, 1]);
+
+        // Another vocabulary starts a new cache.
+        let other = TokenIndex::new(&TokenVocabulary::new(vec![b"7".to_vec()], Vec::new()));
+        assert_eq!(allowed(&mut constraint, &other), [0]);
+        assert_eq!(constraint.index_id, Some(other.id()));
+        assert_eq!(constraint.allowed.len(), 1);
+    }
+
+    #[test]
+    fn clones_advance_independently() {
+        let start = RegexConstraint::new("a|b").unwrap();
+        let mut a = start.clone();
+        a.advance(b"a").unwrap();
+        assert!(a.is_finished());
+        assert!(start.allows(b"b"));
+        assert!(!start.can_finish());
+    }
+}
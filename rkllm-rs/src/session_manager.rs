@@ -0,0 +1,364 @@
+use crate::prelude::{ChatSession, LLMHandle, RkllmError};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+pub const DEFAULT_SESSION_CAPACITY: usize = 8;
+
+struct Sessions<'a> {
+    by_id: HashMap<String, Arc<Mutex<ChatSession<'a>>>>,
+    // Least recently used first.
+    order: VecDeque<String>,
+}
+
+impl Sessions<'_> {
+    fn touch(&mut self, id: &str) {
+        if let Some(pos) = self.order.iter().position(|used| used == id) {
+            if let Some(id) = self.order.remove(pos) {
+                self.order.push_back(id);
+            }
+        }
+    }
+
+    fn remove(&mut self, id: &str) -> bool {
+        self.order.retain(|used| used != id);
+        self.by_id.remove(id).is_some()
+    }
+}
+
+// Keeps several conversations on one handle. Only one of them can occupy the KV cache, so turns
+// are serialized: each runs under a handle-wide lock, also across sessions, and the session is
+// swapped in first by replaying its history through the handle's chat renderer, which `insert`
+// checks for. Use one handle per conversation to run them in parallel.
+pub struct SessionManager<'a> {
+    handle: &'a LLMHandle,
+    capacity: usize,
+    cache_dir: Option<PathBuf>,
+    sessions: Mutex<Sessions<'a>>,
+    // Id of the session whose state is in the KV cache.
+    active: Mutex<Option<String>>,
+}
+
+impl<'a> SessionManager<'a> {
+    pub fn new(handle: &'a LLMHandle) -> Self {
+        Self {
+            handle,
+            capacity: DEFAULT_SESSION_CAPACITY,
+            cache_dir: None,
+            sessions: Mutex::new(Sessions {
+                by_id: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+            active: Mutex::new(None),
+        }
+    }
+
+    // Inserting beyond `capacity` evicts the least recently used session.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    // Sessions without a prompt cache get `<cache_dir>/<id>.cache`.
+    pub fn with_cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(cache_dir.into());
+        self
+    }
+
+    pub fn handle(&self) -> &'a LLMHandle {
+        self.handle
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock_sessions().by_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.lock_sessions().by_id.contains_key(id)
+    }
+
+    // Most recently used first.
+    pub fn ids(&self) -> Vec<String> {
+        self.lock_sessions().order.iter().rev().cloned().collect()
+    }
+
+    pub fn active(&self) -> Option<String> {
+        self.lock_active().clone()
+    }
+
+    // Returns the ids evicted to stay within capacity.
+    pub fn insert(
+        &self,
+        id: impl Into<String>,
+        session: ChatSession<'a>,
+    ) -> Result<Vec<String>, RkllmError> {
+        if !std::ptr::eq(session.handle(), self.handle) {
+            return Err(RkllmError::invalid_argument(
+                "session belongs to a different handle",
+            ));
+        }
+        let id = id.into();
+        let session = match (&self.cache_dir, session.prompt_cache_path()) {
+            (Some(cache_dir), None) => {
+                let file_name = format!("{}.cache", sanitize_file_name(&id));
+                session.with_prompt_cache(cache_dir.join(file_name).to_string_lossy())
+            }
+            _ => session,
+        };
+        if self.handle.chat_renderer().is_none() {
+            return Err(RkllmError::invalid_argument(format!(
+                "the handle has no chat renderer to replay session {}",
+                id
+            )));
+        }
+
+        let mut evicted = Vec::new();
+        {
+            let mut sessions = self.lock_sessions();
+            sessions.remove(&id);
+            sessions
+                .by_id
+                .insert(id.clone(), Arc::new(Mutex::new(session)));
+            sessions.order.push_back(id.clone());
+            while sessions.by_id.len() > self.capacity {
+                let Some(oldest) = sessions.order.pop_front() else {
+                    break;
+                };
+                if let Some(session) = sessions.by_id.remove(&oldest) {
+                    evicted.push((oldest, session));
+                }
+            }
+        }
+        self.forget_active(&id);
+        // Outside the map lock: discarding waits for turns still running on the session.
+        Ok(evicted
+            .into_iter()
+            .map(|(id, session)| {
+                self.discard(&id, &session);
+                id
+            })
+            .collect())
+    }
+
+    pub fn remove(&self, id: &str) -> bool {
+        let session = {
+            let mut sessions = self.lock_sessions();
+            let session = sessions.by_id.get(id).cloned();
+            sessions.remove(id);
+            session
+        };
+        match session {
+            Some(session) => {
+                self.discard(id, &session);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn with_session<R>(
+        &self,
+        id: &str,
+        func: impl FnOnce(&mut ChatSession<'a>) -> Result<R, RkllmError>,
+    ) -> Result<R, RkllmError> {
+        let session = {
+            let mut sessions = self.lock_sessions();
+            let session =
+                sessions.by_id.get(id).cloned().ok_or_else(|| {
+                    RkllmError::invalid_argument(format!("unknown session {}", id))
+                })?;
+            sessions.touch(id);
+            session
+        };
+
+        // Always session first, then the handle, so two callers cannot deadlock.
+        let mut session = session
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Held for the whole turn: another session's turn would overwrite the KV cache.
+        let mut active = self.lock_active();
+        if active.as_deref() != Some(id) {
+            session.reattach();
+            *active = Some(id.to_owned());
+        }
+        func(&mut session)
+    }
+
+    pub fn send(&self, id: &str, prompt: impl Into<String>) -> Result<String, RkllmError> {
+        self.with_session(id, |session| session.send(prompt))
+    }
+
+    pub fn send_tool_result(
+        &self,
+        id: &str,
+        content: impl Into<String>,
+    ) -> Result<String, RkllmError> {
+        self.with_session(id, |session| session.send_tool_result(content))
+    }
+
+    fn discard(&self, id: &str, session: &Mutex<ChatSession<'a>>) {
+        let cache_path = session
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .prompt_cache_path()
+            .map(PathBuf::from);
+        self.forget_active(id);
+        // Only prompt caches the manager placed itself are removed.
+        if let (Some(cache_dir), Some(cache_path)) = (&self.cache_dir, cache_path) {
+            if cache_path.starts_with(cache_dir) {
+                let _ = fs::remove_file(cache_path);
+            }
+        }
+    }
+
+    fn forget_active(&self, id: &str) {
+        let mut active = self.lock_active();
+        if active.as_deref() == Some(id) {
+            *active = None;
+        }
+    }
+
+    fn lock_sessions(&self) -> MutexGuard<'_, Sessions<'a>> {
+        self.sessions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_active(&self) -> MutexGuard<'_, Option<String>> {
+        self.active
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn sanitize_file_name(id: &str) -> String {
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::{ChatRenderer, ChatTemplate, LLMConfig};
+
+    fn detached(with_renderer: bool) -> LLMHandle {
+        let renderer: Option<Arc<dyn ChatRenderer>> =
+            with_renderer.then(|| Arc::new(ChatTemplate::CHATML) as Arc<dyn ChatRenderer>);
+        LLMHandle::detached(LLMConfig::default(), renderer)
+    }
+
+    fn insert(manager: &SessionManager<'_>, id: &str) -> Vec<String> {
+        manager
+            .insert(id, ChatSession::new(manager.handle()))
+            .unwrap()
+    }
+
+    fn touch(manager: &SessionManager<'_>, id: &str) {
+        manager.with_session(id, |_| Ok(())).unwrap();
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_session() {
+        let handle = detached(true);
+        let manager = SessionManager::new(&handle).with_capacity(2);
+        assert!(insert(&manager, "a").is_empty());
+        assert!(insert(&manager, "b").is_empty());
+        touch(&manager, "a");
+
+        assert_eq!(insert(&manager, "c"), ["b"]);
+        assert_eq!(manager.ids(), ["c", "a"]);
+        assert!(!manager.contains("b"));
+    }
+
+    #[test]
+    fn reinserting_replaces_the_session() {
+        let handle = detached(true);
+        let manager = SessionManager::new(&handle).with_capacity(2);
+        insert(&manager, "a");
+        insert(&manager, "b");
+
+        assert!(insert(&manager, "a").is_empty());
+        assert_eq!(manager.ids(), ["a", "b"]);
+        assert_eq!(manager.len(), 2);
+    }
+
+    #[test]
+    fn swaps_in_the_session_that_runs() {
+        let handle = detached(true);
+        let manager = SessionManager::new(&handle);
+        insert(&manager, "a");
+        insert(&manager, "b");
+        assert_eq!(manager.active(), None);
+
+        touch(&manager, "a");
+        assert_eq!(manager.active().as_deref(), Some("a"));
+        touch(&manager, "b");
+        assert_eq!(manager.active().as_deref(), Some("b"));
+        assert!(matches!(
+            manager.with_session("c", |_| Ok(())),
+            Err(RkllmError::InvalidArgument(_))
+        ));
+        assert_eq!(manager.active().as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn removing_the_active_session_forgets_it() {
+        let handle = detached(true);
+        let manager = SessionManager::new(&handle);
+        insert(&manager, "a");
+        touch(&manager, "a");
+
+        assert!(manager.remove("a"));
+        assert!(!manager.remove("a"));
+        assert_eq!(manager.active(), None);
+        assert!(manager.is_empty());
+    }
+
+    #[test]
+    fn eviction_removes_only_managed_prompt_caches() {
+        let cache_dir = std::env::temp_dir().join(format!("rkllm-sessions-{}", std::process::id()));
+        fs::create_dir_all(&cache_dir).unwrap();
+        let own_cache = cache_dir.with_extension("own");
+        fs::write(&own_cache, b"").unwrap();
+        let managed_cache = cache_dir.join("a_b.cache");
+        fs::write(&managed_cache, b"").unwrap();
+
+        let handle = detached(true);
+        let manager = SessionManager::new(&handle)
+            .with_capacity(1)
+            .with_cache_dir(&cache_dir);
+        insert(&manager, "a/b");
+        let own = ChatSession::new(&handle).with_prompt_cache(own_cache.to_string_lossy());
+        assert_eq!(manager.insert("own", own).unwrap(), ["a/b"]);
+        insert(&manager, "c");
+
+        assert!(!managed_cache.exists());
+        assert!(own_cache.exists());
+        fs::remove_file(own_cache).unwrap();
+        fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn insert_needs_a_chat_renderer() {
+        let handle = detached(false);
+        let manager = SessionManager::new(&handle);
+        assert!(manager.insert("a", ChatSession::new(&handle)).is_err());
+        assert!(manager.is_empty());
+    }
+}
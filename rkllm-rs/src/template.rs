@@ -0,0 +1,531 @@
+use crate::prelude::{LLMHandle, RKLLMInputRole, RkllmError};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: RKLLMInputRole,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: RKLLMInputRole, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+        }
+    }
+
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new(RKLLMInputRole::System, content)
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(RKLLMInputRole::User, content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new(RKLLMInputRole::Assistant, content)
+    }
+
+    pub fn tool(content: impl Into<String>) -> Self {
+        Self::new(RKLLMInputRole::Tool, content)
+    }
+}
+
+pub trait ChatRenderer: fmt::Debug + Send + Sync {
+    fn render(
+        &self,
+        messages: &[ChatMessage],
+        add_generation_prompt: bool,
+        enable_thinking: bool,
+    ) -> Result<String, RkllmError>;
+
+    // Renders messages appended to a conversation already in the KV cache, so the BOS token and
+    // any default system prompt must not be repeated. Renderers without a preamble can rely on
+    // the default.
+    fn render_continuation(
+        &self,
+        messages: &[ChatMessage],
+        add_generation_prompt: bool,
+        enable_thinking: bool,
+    ) -> Result<String, RkllmError> {
+        self.render(messages, add_generation_prompt, enable_thinking)
+    }
+
+    // Lets a marker-based renderer be saved along with a conversation.
+    fn chat_template(&self) -> Option<&ChatTemplate> {
+        None
+    }
+}
+
+// The three strings passed to `rkllm_set_chat_template`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuntimeChatTemplate {
+    pub system_prompt: String,
+    pub prompt_prefix: String,
+    pub prompt_postfix: String,
+}
+
+// Role markers of a chat template. `custom_prefix`/`custom_suffix` may contain `{role}`, which is
+// replaced with the name of an `RKLLMInputRole::Custom` role.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatTemplate {
+    pub name: Cow<'static, str>,
+    pub bos_token: Cow<'static, str>,
+    pub default_system_prompt: Cow<'static, str>,
+    pub system_prefix: Cow<'static, str>,
+    pub system_suffix: Cow<'static, str>,
+    pub user_prefix: Cow<'static, str>,
+    pub user_suffix: Cow<'static, str>,
+    pub assistant_prefix: Cow<'static, str>,
+    pub assistant_suffix: Cow<'static, str>,
+    pub tool_prefix: Cow<'static, str>,
+    pub tool_suffix: Cow<'static, str>,
+    pub custom_prefix: Cow<'static, str>,
+    pub custom_suffix: Cow<'static, str>,
+    pub thinking_disabled: Cow<'static, str>,
+}
+
+const EMPTY: ChatTemplate = ChatTemplate {
+    name: Cow::Borrowed(""),
+    bos_token: Cow::Borrowed(""),
+    default_system_prompt: Cow::Borrowed(""),
+    system_prefix: Cow::Borrowed(""),
+    system_suffix: Cow::Borrowed(""),
+    user_prefix: Cow::Borrowed(""),
+    user_suffix: Cow::Borrowed(""),
+    assistant_prefix: Cow::Borrowed(""),
+    assistant_suffix: Cow::Borrowed(""),
+    tool_prefix: Cow::Borrowed(""),
+    tool_suffix: Cow::Borrowed(""),
+    custom_prefix: Cow::Borrowed(""),
+    custom_suffix: Cow::Borrowed(""),
+    thinking_disabled: Cow::Borrowed(""),
+};
+
+impl ChatTemplate {
+    pub const CHATML: ChatTemplate = ChatTemplate {
+        name: Cow::Borrowed("chatml"),
+        system_prefix: Cow::Borrowed("<|im_start|>system\n"),
+        system_suffix: Cow::Borrowed("<|im_end|>\n"),
+        user_prefix: Cow::Borrowed("<|im_start|>user\n"),
+        user_suffix: Cow::Borrowed("<|im_end|>\n"),
+        assistant_prefix: Cow::Borrowed("<|im_start|>assistant\n"),
+        assistant_suffix: Cow::Borrowed("<|im_end|>\n"),
+        tool_prefix: Cow::Borrowed("<|im_start|>tool\n"),
+        tool_suffix: Cow::Borrowed("<|im_end|>\n"),
+        custom_prefix: Cow::Borrowed("<|im_start|>{role}\n"),
+        custom_suffix: Cow::Borrowed("<|im_end|>\n"),
+        ..EMPTY
+    };
+
+    pub const DEEPSEEK_R1: ChatTemplate = ChatTemplate {
+        name: Cow::Borrowed("deepseek-r1"),
+        bos_token: Cow::Borrowed("<｜begin▁of▁sentence｜>"),
+        user_prefix: Cow::Borrowed("<｜User｜>"),
+        assistant_prefix: Cow::Borrowed("<｜Assistant｜>"),
+        assistant_suffix: Cow::Borrowed("<｜end▁of▁sentence｜>"),
+        tool_prefix: Cow::Borrowed("<｜tool▁outputs▁begin｜><｜tool▁output▁begin｜>"),
+        tool_suffix: Cow::Borrowed("<｜tool▁output▁end｜><｜tool▁outputs▁end｜>"),
+        custom_prefix: Cow::Borrowed("<｜User｜>"),
+        ..EMPTY
+    };
+
+    pub const QWEN2: ChatTemplate = ChatTemplate {
+        name: Cow::Borrowed("qwen2"),
+        default_system_prompt: Cow::Borrowed("You are a helpful assistant."),
+        ..Self::CHATML
+    };
+
+    pub const QWEN2_5: ChatTemplate = ChatTemplate {
+        name: Cow::Borrowed("qwen2.5"),
+        default_system_prompt: Cow::Borrowed(
+            "You are Qwen, created by Alibaba Cloud. You are a helpful assistant.",
+        ),
+        tool_prefix: Cow::Borrowed("<|im_start|>user\n<tool_response>\n"),
+        tool_suffix: Cow::Borrowed("\n</tool_response><|im_end|>\n"),
+        ..Self::CHATML
+    };
+
+    pub const QWEN3: ChatTemplate = ChatTemplate {
+        name: Cow::Borrowed("qwen3"),
+        tool_prefix: Cow::Borrowed("<|im_start|>user\n<tool_response>\n"),
+        tool_suffix: Cow::Borrowed("\n</tool_response><|im_end|>\n"),
+        thinking_disabled: Cow::Borrowed("<think>\n\n</think>\n\n"),
+        ..Self::CHATML
+    };
+
+    pub const LLAMA3: ChatTemplate = ChatTemplate {
+        name: Cow::Borrowed("llama3"),
+        bos_token: Cow::Borrowed("<|begin_of_text|>"),
+        system_prefix: Cow::Borrowed("<|start_header_id|>system<|end_header_id|>\n\n"),
+        system_suffix: Cow::Borrowed("<|eot_id|>"),
+        user_prefix: Cow::Borrowed("<|start_header_id|>user<|end_header_id|>\n\n"),
+        user_suffix: Cow::Borrowed("<|eot_id|>"),
+        assistant_prefix: Cow::Borrowed("<|start_header_id|>assistant<|end_header_id|>\n\n"),
+        assistant_suffix: Cow::Borrowed("<|eot_id|>"),
+        tool_prefix: Cow::Borrowed("<|start_header_id|>ipython<|end_header_id|>\n\n"),
+        tool_suffix: Cow::Borrowed("<|eot_id|>"),
+        custom_prefix: Cow::Borrowed("<|start_header_id|>{role}<|end_header_id|>\n\n"),
+        custom_suffix: Cow::Borrowed("<|eot_id|>"),
+        ..EMPTY
+    };
+
+    // Gemma has no system role; system instructions go into a user turn of their own.
+    pub const GEMMA2: ChatTemplate = ChatTemplate {
+        name: Cow::Borrowed("gemma2"),
+        bos_token: Cow::Borrowed("<bos>"),
+        system_prefix: Cow::Borrowed("<start_of_turn>user\n"),
+        system_suffix: Cow::Borrowed("<end_of_turn>\n"),
+        user_prefix: Cow::Borrowed("<start_of_turn>user\n"),
+        user_suffix: Cow::Borrowed("<end_of_turn>\n"),
+        assistant_prefix: Cow::Borrowed("<start_of_turn>model\n"),
+        assistant_suffix: Cow::Borrowed("<end_of_turn>\n"),
+        tool_prefix: Cow::Borrowed("<start_of_turn>user\n"),
+        tool_suffix: Cow::Borrowed("<end_of_turn>\n"),
+        custom_prefix: Cow::Borrowed("<start_of_turn>user\n"),
+        custom_suffix: Cow::Borrowed("<end_of_turn>\n"),
+        ..EMPTY
+    };
+
+    pub const GEMMA3: ChatTemplate = ChatTemplate {
+        name: Cow::Borrowed("gemma3"),
+        ..Self::GEMMA2
+    };
+
+    pub const PHI3: ChatTemplate = ChatTemplate {
+        name: Cow::Borrowed("phi3"),
+        system_prefix: Cow::Borrowed("<|system|>\n"),
+        system_suffix: Cow::Borrowed("<|end|>\n"),
+        user_prefix: Cow::Borrowed("<|user|>\n"),
+        user_suffix: Cow::Borrowed("<|end|>\n"),
+        assistant_prefix: Cow::Borrowed("<|assistant|>\n"),
+        assistant_suffix: Cow::Borrowed("<|end|>\n"),
+        tool_prefix: Cow::Borrowed("<|user|>\n"),
+        tool_suffix: Cow::Borrowed("<|end|>\n"),
+        custom_prefix: Cow::Borrowed("<|{role}|>\n"),
+        custom_suffix: Cow::Borrowed("<|end|>\n"),
+        ..EMPTY
+    };
+
+    pub const MINICPM: ChatTemplate = ChatTemplate {
+        name: Cow::Borrowed("minicpm"),
+        ..Self::CHATML
+    };
+
+    pub const CHATGLM: ChatTemplate = ChatTemplate {
+        name: Cow::Borrowed("chatglm"),
+        bos_token: Cow::Borrowed("[gMASK]<sop>"),
+        system_prefix: Cow::Borrowed("<|system|>\n"),
+        user_prefix: Cow::Borrowed("<|user|>\n"),
+        assistant_prefix: Cow::Borrowed("<|assistant|>\n"),
+        tool_prefix: Cow::Borrowed("<|observation|>\n"),
+        custom_prefix: Cow::Borrowed("<|{role}|>\n"),
+        ..EMPTY
+    };
+
+    pub const INTERNLM: ChatTemplate = ChatTemplate {
+        name: Cow::Borrowed("internlm"),
+        bos_token: Cow::Borrowed("<s>"),
+        tool_prefix: Cow::Borrowed("<|im_start|>environment\n"),
+        ..Self::CHATML
+    };
+
+    pub fn builtin() -> &'static [ChatTemplate] {
+        &BUILTIN_TEMPLATES
+    }
+
+    pub fn by_name(name: &str) -> Option<&'static ChatTemplate> {
+        BUILTIN_TEMPLATES
+            .iter()
+            .find(|template| template.name.eq_ignore_ascii_case(name))
+    }
+
+    // Guesses the template from a model path such as
+    // `DeepSeek-R1-Distill-Qwen-1.5B_W8A8_RK3588.rkllm`.
+    pub fn detect(model_path: impl AsRef<Path>) -> Option<&'static ChatTemplate> {
+        let file_name = model_path.as_ref().file_name()?.to_str()?;
+        let normalized: String = file_name
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        DETECTION_PATTERNS
+            .iter()
+            .find(|(pattern, _)| normalized.contains(pattern))
+            .map(|(_, template)| *template)
+    }
+
+    // The runtime template is `system_prompt` once, then `prompt_prefix` + input +
+    // `prompt_postfix` per turn.
+    pub fn runtime_template(&self, system_prompt: Option<&str>) -> RuntimeChatTemplate {
+        let system_prompt = system_prompt.unwrap_or(&self.default_system_prompt);
+        let mut system = self.bos_token.to_string();
+        if !system_prompt.is_empty() {
+            system.push_str(&self.system_prefix);
+            system.push_str(system_prompt);
+            system.push_str(&self.system_suffix);
+        }
+        RuntimeChatTemplate {
+            system_prompt: system,
+            prompt_prefix: self.user_prefix.to_string(),
+            prompt_postfix: format!("{}{}", self.user_suffix, self.assistant_prefix),
+        }
+    }
+
+    pub fn render_message(&self, message: &ChatMessage) -> String {
+        let (prefix, suffix) = match &message.role {
+            RKLLMInputRole::System => (self.system_prefix.clone(), &self.system_suffix),
+            RKLLMInputRole::User => (self.user_prefix.clone(), &self.user_suffix),
+            RKLLMInputRole::Assistant => (self.assistant_prefix.clone(), &self.assistant_suffix),
+            RKLLMInputRole::Tool => (self.tool_prefix.clone(), &self.tool_suffix),
+            RKLLMInputRole::Custom(role) => (
+                Cow::Owned(self.custom_prefix.replace("{role}", role)),
+                &self.custom_suffix,
+            ),
+        };
+        let mut rendered =
+            String::with_capacity(prefix.len() + message.content.len() + suffix.len());
+        rendered.push_str(&prefix);
+        rendered.push_str(&message.content);
+        rendered.push_str(suffix);
+        rendered
+    }
+}
+
+static BUILTIN_TEMPLATES: [ChatTemplate; 12] = [
+    ChatTemplate::CHATML,
+    ChatTemplate::DEEPSEEK_R1,
+    ChatTemplate::QWEN2,
+    ChatTemplate::QWEN2_5,
+    ChatTemplate::QWEN3,
+    ChatTemplate::LLAMA3,
+    ChatTemplate::GEMMA2,
+    ChatTemplate::GEMMA3,
+    ChatTemplate::PHI3,
+    ChatTemplate::MINICPM,
+    ChatTemplate::CHATGLM,
+    ChatTemplate::INTERNLM,
+];
+
+// Matched against the lowercase alphanumeric characters of the file name, first match wins, so
+// distilled models (`deepseek-r1-distill-qwen`) come before their base family.
+static DETECTION_PATTERNS: [(&str, &ChatTemplate); 12] = [
+    ("deepseek", &ChatTemplate::DEEPSEEK_R1),
+    ("qwen3", &ChatTemplate::QWEN3),
+    ("qwen25", &ChatTemplate::QWEN2_5),
+    ("qwen2", &ChatTemplate::QWEN2),
+    ("qwen", &ChatTemplate::QWEN2),
+    ("llama3", &ChatTemplate::LLAMA3),
+    ("gemma3", &ChatTemplate::GEMMA3),
+    ("gemma", &ChatTemplate::GEMMA2),
+    ("phi3", &ChatTemplate::PHI3),
+    ("minicpm", &ChatTemplate::MINICPM),
+    ("chatglm", &ChatTemplate::CHATGLM),
+    ("internlm", &ChatTemplate::INTERNLM),
+];
+
+impl ChatRenderer for ChatTemplate {
+    fn render(
+        &self,
+        messages: &[ChatMessage],
+        add_generation_prompt: bool,
+        enable_thinking: bool,
+    ) -> Result<String, RkllmError> {
+        let mut rendered = self.bos_token.to_string();
+        let has_system = messages
+            .first()
+            .is_some_and(|message| message.role == RKLLMInputRole::System);
+        if !has_system && !self.default_system_prompt.is_empty() {
+            rendered.push_str(
+                &self.render_message(&ChatMessage::system(self.default_system_prompt.clone())),
+            );
+        }
+        rendered.push_str(&self.render_continuation(
+            messages,
+            add_generation_prompt,
+            enable_thinking,
+        )?);
+        Ok(rendered)
+    }
+
+    fn render_continuation(
+        &self,
+        messages: &[ChatMessage],
+        add_generation_prompt: bool,
+        enable_thinking: bool,
+    ) -> Result<String, RkllmError> {
+        let mut rendered = String::new();
+        for message in messages {
+            rendered.push_str(&self.render_message(message));
+        }
+        if add_generation_prompt {
+            rendered.push_str(&self.assistant_prefix);
+            if !enable_thinking {
+                rendered.push_str(&self.thinking_disabled);
+            }
+        }
+        Ok(rendered)
+    }
+
+    fn chat_template(&self) -> Option<&ChatTemplate> {
+        Some(self)
+    }
+}
+
+impl LLMHandle {
+    pub fn apply_chat_template(
+        &self,
+        template: &ChatTemplate,
+        system_prompt: Option<&str>,
+    ) -> Result<(), RkllmError> {
+        let template = template.runtime_template(system_prompt);
+        self.set_chat_template(
+            &template.system_prompt,
+            &template.prompt_prefix,
+            &template.prompt_postfix,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_templates_from_model_file_names() {
+        let cases = [
+            (
+                "DeepSeek-R1-Distill-Qwen-1.5B_W8A8_RK3588.rkllm",
+                "deepseek-r1",
+            ),
+            ("Qwen3-4B_w8a8_rk3588.rkllm", "qwen3"),
+            ("Qwen2.5-3B-Instruct.rkllm", "qwen2.5"),
+            ("Qwen2-VL-2B-Instruct.rkllm", "qwen2"),
+            ("qwen-1_8b-chat.rkllm", "qwen2"),
+            ("Llama-3.2-1B-Instruct.rkllm", "llama3"),
+            ("gemma-3-1b-it.rkllm", "gemma3"),
+            ("gemma-2-2b-it.rkllm", "gemma2"),
+            ("Phi-3-mini-4k-instruct.rkllm", "phi3"),
+            ("MiniCPM4-0.5B.rkllm", "minicpm"),
+            ("chatglm3-6b.rkllm", "chatglm"),
+            ("internlm2_5-1_8b-chat.rkllm", "internlm"),
+            ("/models/qwen3/TinyLlama-1.1B.rkllm", ""),
+        ];
+        for (path, expected) in cases {
+            let detected = ChatTemplate::detect(path).map(|template| template.name.as_ref());
+            assert_eq!(detected.unwrap_or_default(), expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn finds_builtin_templates_by_name() {
+        for template in ChatTemplate::builtin() {
+            assert_eq!(ChatTemplate::by_name(&template.name), Some(template));
+        }
+        assert_eq!(
+            ChatTemplate::by_name("Qwen2.5").map(|template| template.name.as_ref()),
+            Some("qwen2.5")
+        );
+        assert_eq!(ChatTemplate::by_name("normal"), None);
+    }
+
+    #[test]
+    fn renders_every_builtin_template() {
+        let chatml = "<|im_start|>user\nhi<|im_end|>\n<|im_start|>assistant\nhello<|im_end|>\n\
+                      <|im_start|>tool\n42<|im_end|>\n<|im_start|>assistant\n";
+        let qwen_turns = "<|im_start|>user\nhi<|im_end|>\n<|im_start|>assistant\nhello<|im_end|>\n\
+                          <|im_start|>user\n<tool_response>\n42\n</tool_response><|im_end|>\n\
+                          <|im_start|>assistant\n";
+        let gemma = "<bos><start_of_turn>user\nhi<end_of_turn>\n<start_of_turn>model\nhello\
+                     <end_of_turn>\n<start_of_turn>user\n42<end_of_turn>\n<start_of_turn>model\n";
+        let cases = [
+            (&ChatTemplate::CHATML, chatml.to_owned()),
+            (
+                &ChatTemplate::DEEPSEEK_R1,
+                "<｜begin▁of▁sentence｜><｜User｜>hi<｜Assistant｜>hello<｜end▁of▁sentence｜>\
+                 <｜tool▁outputs▁begin｜><｜tool▁output▁begin｜>42<｜tool▁output▁end｜>\
+                 <｜tool▁outputs▁end｜><｜Assistant｜>"
+                    .to_owned(),
+            ),
+            (
+                &ChatTemplate::QWEN2,
+                format!(
+                    "<|im_start|>system\nYou are a helpful assistant.<|im_end|>\n{}",
+                    chatml
+                ),
+            ),
+            (
+                &ChatTemplate::QWEN2_5,
+                format!(
+                    "<|im_start|>system\nYou are Qwen, created by Alibaba Cloud. You are a \
+                     helpful assistant.<|im_end|>\n{}",
+                    qwen_turns
+                ),
+            ),
+            (
+                &ChatTemplate::QWEN3,
+                format!("{}<think>\n\n</think>\n\n", qwen_turns),
+            ),
+            (
+                &ChatTemplate::LLAMA3,
+                "<|begin_of_text|><|start_header_id|>user<|end_header_id|>\n\nhi<|eot_id|>\
+                 <|start_header_id|>assistant<|end_header_id|>\n\nhello<|eot_id|>\
+                 <|start_header_id|>ipython<|end_header_id|>\n\n42<|eot_id|>\
+                 <|start_header_id|>assistant<|end_header_id|>\n\n"
+                    .to_owned(),
+            ),
+            (&ChatTemplate::GEMMA2, gemma.to_owned()),
+            (&ChatTemplate::GEMMA3, gemma.to_owned()),
+            (
+                &ChatTemplate::PHI3,
+                "<|user|>\nhi<|end|>\n<|assistant|>\nhello<|end|>\n<|user|>\n42<|end|>\n\
+                 <|assistant|>\n"
+                    .to_owned(),
+            ),
+            (&ChatTemplate::MINICPM, chatml.to_owned()),
+            (
+                &ChatTemplate::CHATGLM,
+                "[gMASK]<sop><|user|>\nhi<|assistant|>\nhello<|observation|>\n42<|assistant|>\n"
+                    .to_owned(),
+            ),
+            (
+                &ChatTemplate::INTERNLM,
+                "<s><|im_start|>user\nhi<|im_end|>\n<|im_start|>assistant\nhello<|im_end|>\n\
+                 <|im_start|>environment\n42<|im_end|>\n<|im_start|>assistant\n"
+                    .to_owned(),
+            ),
+        ];
+        assert_eq!(cases.len(), ChatTemplate::builtin().len());
+
+        let messages = [
+            ChatMessage::user("hi"),
+            ChatMessage::assistant("hello"),
+            ChatMessage::tool("42"),
+        ];
+        for (template, expected) in cases {
+            let rendered = template.render(&messages, true, false).unwrap();
+            assert_eq!(rendered, expected, "{}", template.name);
+        }
+    }
+
+    #[test]
+    fn explicit_system_prompt_replaces_the_default() {
+        let rendered = ChatTemplate::QWEN2
+            .render(&[ChatMessage::system("be brief")], false, true)
+            .unwrap();
+        assert_eq!(rendered, "<|im_start|>system\nbe brief<|im_end|>\n");
+    }
+
+    #[test]
+    fn custom_roles_are_named_in_the_prefix() {
+        let message = ChatMessage::new(RKLLMInputRole::Custom("critic".into()), "ok");
+        assert_eq!(
+            ChatTemplate::CHATML.render_message(&message),
+            "<|im_start|>critic\nok<|im_end|>\n"
+        );
+    }
+}
@@ -0,0 +1,609 @@
+use crate::prelude::{ChatTemplate, ReasoningChunk, ReasoningParser};
+use crate::reasoning::partial_tag_len;
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,
+}
+
+// Syntax a model family uses to emit tool calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToolCallFormat {
+    // Any of the tagged formats below, falling back to a reply that is a single JSON call.
+    #[default]
+    Auto,
+    // `<tool_call>{"name": ..., "arguments": ...}</tool_call>` (Qwen, Hermes, ChatML models).
+    Hermes,
+    // `<|python_tag|>{"name": ..., "parameters": ...}` up to `<|eom_id|>`, or a bare JSON call.
+    Llama3,
+    // `<｜tool▁call▁begin｜>function<｜tool▁sep｜>name` followed by fenced JSON arguments.
+    DeepSeek,
+    // JSON call objects (or arrays of them) anywhere in the text.
+    Json,
+}
+
+impl ToolCallFormat {
+    pub fn for_template(template: &ChatTemplate) -> Self {
+        Self::by_name(&template.name)
+    }
+
+    // Maps a chat template name (see `ChatTemplate::by_name`) to its family's format.
+    pub fn by_name(name: &str) -> Self {
+        let name = name.to_ascii_lowercase();
+        if name.starts_with("deepseek") {
+            ToolCallFormat::DeepSeek
+        } else if name.starts_with("qwen") || name.starts_with("chatml") || name == "hermes" {
+            ToolCallFormat::Hermes
+        } else if name.starts_with("llama3") {
+            ToolCallFormat::Llama3
+        } else if name == "json" {
+            ToolCallFormat::Json
+        } else {
+            ToolCallFormat::Auto
+        }
+    }
+
+    fn blocks(self) -> &'static [Block] {
+        match self {
+            ToolCallFormat::Auto => &ALL_BLOCKS,
+            ToolCallFormat::Hermes => &ALL_BLOCKS[..1],
+            ToolCallFormat::Llama3 => &ALL_BLOCKS[1..2],
+            ToolCallFormat::DeepSeek => &ALL_BLOCKS[2..],
+            ToolCallFormat::Json => &[],
+        }
+    }
+
+    // Llama 3.2 and smaller models often answer with a bare JSON call instead of a tag.
+    fn accepts_bare_json(self) -> bool {
+        matches!(self, ToolCallFormat::Auto | ToolCallFormat::Llama3)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockBody {
+    Json,
+    DeepSeek,
+}
+
+// Text between `open` and the first of `close`. Markers in `skip` only wrap blocks and are
+// dropped.
+#[derive(Debug)]
+struct Block {
+    open: &'static str,
+    close: &'static [&'static str],
+    skip: &'static [&'static str],
+    body: BlockBody,
+}
+
+static ALL_BLOCKS: [Block; 3] = [
+    Block {
+        open: "<tool_call>",
+        close: &["</tool_call>"],
+        skip: &[],
+        body: BlockBody::Json,
+    },
+    Block {
+        open: "<|python_tag|>",
+        close: &["<|eom_id|>", "<|eot_id|>"],
+        skip: &[],
+        body: BlockBody::Json,
+    },
+    Block {
+        open: "<｜tool▁call▁begin｜>",
+        close: &["<｜tool▁call▁end｜>"],
+        skip: &["<｜tool▁calls▁begin｜>", "<｜tool▁calls▁end｜>"],
+        body: BlockBody::DeepSeek,
+    },
+];
+
+const DEEPSEEK_TOOL_SEP: &str = "<｜tool▁sep｜>";
+
+// Extracts tool calls from streamed model output. Text is pushed as it arrives; a call is
+// returned once its block is complete, and text that may still turn into a marker is held back,
+// so markers split across callbacks are found. Reasoning is skipped and the prose around the
+// calls is collected in `content()`.
+#[derive(Debug, Clone)]
+pub struct ToolCallParser {
+    format: ToolCallFormat,
+    reasoning: ReasoningParser,
+    pending: String,
+    content: String,
+    calls: usize,
+}
+
+impl ToolCallParser {
+    pub fn new(format: ToolCallFormat) -> Self {
+        Self {
+            format,
+            reasoning: ReasoningParser::new(),
+            pending: String::new(),
+            content: String::new(),
+            calls: 0,
+        }
+    }
+
+    // See `ReasoningParser::starting_in_reasoning`.
+    pub fn with_reasoning(mut self, reasoning: ReasoningParser) -> Self {
+        self.reasoning = reasoning;
+        self
+    }
+
+    pub fn format(&self) -> ToolCallFormat {
+        self.format
+    }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    pub fn push(&mut self, text: &str) -> Vec<ToolCall> {
+        let chunks = self.reasoning.push(text);
+        self.push_answer(chunks);
+        self.scan(false)
+    }
+
+    // Flushes held back text; an unterminated block at the end of the output is still parsed.
+    pub fn finish(&mut self) -> Vec<ToolCall> {
+        let chunks = self.reasoning.finish();
+        self.push_answer(chunks);
+        let mut calls = self.scan(true);
+        if self.calls == 0 && self.format.accepts_bare_json() {
+            let bare = json_calls(self.content.trim());
+            // Only a reply that is nothing but calls counts, not JSON quoted in prose.
+            if !bare.is_empty() && is_json(self.content.trim()) {
+                self.content.clear();
+                calls = bare.into_iter().map(|call| self.assign_id(call)).collect();
+            }
+        }
+        calls
+    }
+
+    fn push_answer(&mut self, chunks: impl IntoIterator<Item = ReasoningChunk>) {
+        for chunk in chunks {
+            if let ReasoningChunk::Answer(answer) = chunk {
+                self.pending.push_str(&answer);
+            }
+        }
+    }
+
+    fn scan(&mut self, at_end: bool) -> Vec<ToolCall> {
+        let found = if self.format == ToolCallFormat::Json {
+            self.scan_json(at_end)
+        } else {
+            self.scan_blocks(at_end)
+        };
+        found.into_iter().map(|call| self.assign_id(call)).collect()
+    }
+
+    fn scan_blocks(&mut self, at_end: bool) -> Vec<ToolCall> {
+        let blocks = self.format.blocks();
+        let mut calls = Vec::new();
+        loop {
+            let next = blocks
+                .iter()
+                .flat_map(|block| {
+                    let skips = block.skip.iter().map(move |skip| (*skip, None));
+                    skips.chain(std::iter::once((block.open, Some(block))))
+                })
+                .filter_map(|(marker, block)| {
+                    self.pending
+                        .find(marker)
+                        .map(|pos| (pos, marker.len(), block))
+                })
+                .min_by_key(|(pos, _, _)| *pos);
+            let Some((pos, marker_len, block)) = next else {
+                let keep = if at_end {
+                    0
+                } else {
+                    blocks
+                        .iter()
+                        .flat_map(|block| block.skip.iter().chain(std::iter::once(&block.open)))
+                        .map(|marker| partial_tag_len(&self.pending, marker))
+                        .max()
+                        .unwrap_or(0)
+                };
+                let text: String = self.pending.drain(..self.pending.len() - keep).collect();
+                self.content.push_str(&text);
+                return calls;
+            };
+
+            let text: String = self.pending.drain(..pos).collect();
+            self.content.push_str(&text);
+            let Some(block) = block else {
+                self.pending.drain(..marker_len);
+                continue;
+            };
+            let body = &self.pending[marker_len..];
+            let close = block
+                .close
+                .iter()
+                .filter_map(|close| body.find(close).map(|end| (end, close.len())))
+                .min_by_key(|(end, _)| *end);
+            match close {
+                Some((end, close_len)) => {
+                    calls.extend(block_calls(block.body, &body[..end]));
+                    self.pending.drain(..marker_len + end + close_len);
+                }
+                None if at_end => {
+                    calls.extend(block_calls(block.body, body));
+                    self.pending.clear();
+                    return calls;
+                }
+                None => return calls,
+            }
+        }
+    }
+
+    fn scan_json(&mut self, at_end: bool) -> Vec<ToolCall> {
+        let mut calls = Vec::new();
+        loop {
+            let Some(pos) = self.pending.find(['{', '[']) else {
+                self.content.push_str(&self.pending);
+                self.pending.clear();
+                return calls;
+            };
+            let text: String = self.pending.drain(..pos).collect();
+            self.content.push_str(&text);
+            match balanced_json_len(&self.pending) {
+                Some(len) => {
+                    let found = json_calls(&self.pending[..len]);
+                    if found.is_empty() {
+                        self.content.push_str(&self.pending[..len]);
+                    }
+                    calls.extend(found);
+                    self.pending.drain(..len);
+                }
+                None if at_end => {
+                    self.content.push_str(&self.pending);
+                    self.pending.clear();
+                    return calls;
+                }
+                None => return calls,
+            }
+        }
+    }
+
+    fn assign_id(&mut self, mut call: ToolCall) -> ToolCall {
+        if call.id.is_empty() {
+            call.id = format!("call_{}", self.calls);
+        }
+        self.calls += 1;
+        call
+    }
+}
+
+// Parses a complete reply.
+pub fn parse_tool_calls(text: &str, format: ToolCallFormat) -> Vec<ToolCall> {
+    let mut parser = ToolCallParser::new(format);
+    let mut calls = parser.push(text);
+    calls.extend(parser.finish());
+    calls
+}
+
+fn block_calls(body: BlockBody, text: &str) -> Vec<ToolCall> {
+    match body {
+        BlockBody::Json => json_calls(text),
+        BlockBody::DeepSeek => deepseek_call(text).into_iter().collect(),
+    }
+}
+
+// `function<｜tool▁sep｜>name\n```json\n{...}\n```` (R1/V3) or `name<｜tool▁sep｜>{...}` (V3.1).
+fn deepseek_call(text: &str) -> Option<ToolCall> {
+    let (kind, rest) = text.split_once(DEEPSEEK_TOOL_SEP)?;
+    let (name, arguments) = match kind.trim() {
+        "" | "function" => rest.split_once('\n').unwrap_or((rest, "")),
+        name => (name, rest),
+    };
+    let arguments = arguments
+        .trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+    let arguments = if arguments.is_empty() {
+        json!({})
+    } else {
+        serde_json::from_str(arguments).ok()?
+    };
+    Some(ToolCall {
+        id: String::new(),
+        name: name.trim().to_owned(),
+        arguments,
+    })
+}
+
+// Calls described by the JSON values in `text`; separators such as `;` between them are skipped.
+fn json_calls(text: &str) -> Vec<ToolCall> {
+    let mut calls = Vec::new();
+    let mut rest = text;
+    while let Some(pos) = rest.find(['{', '[']) {
+        rest = &rest[pos..];
+        let mut values = serde_json::Deserializer::from_str(rest).into_iter::<Value>();
+        match values.next() {
+            Some(Ok(value)) => {
+                let end = values.byte_offset();
+                collect_calls(value, &mut calls);
+                rest = &rest[end..];
+            }
+            _ => rest = &rest[1..],
+        }
+    }
+    calls
+}
+
+fn collect_calls(value: Value, calls: &mut Vec<ToolCall>) {
+    match value {
+        Value::Array(values) => {
+            for value in values {
+                collect_calls(value, calls);
+            }
+        }
+        Value::Object(mut object) => {
+            // OpenAI style `{"type": "function", "function": {...}}`.
+            if let Some(Value::Object(mut function)) = object.remove("function") {
+                if let Some(id) = object.remove("id") {
+                    function.insert("id".to_owned(), id);
+                }
+                object = function;
+            }
+            let Some(Value::String(name)) = object.remove("name") else {
+                return;
+            };
+            let arguments = match object
+                .remove("arguments")
+                .or_else(|| object.remove("parameters"))
+            {
+                // Some models encode the arguments as a JSON string.
+                Some(Value::String(arguments)) => match serde_json::from_str(&arguments) {
+                    Ok(arguments) => arguments,
+                    Err(_) => return,
+                },
+                Some(arguments) => arguments,
+                None => json!({}),
+            };
+            let id = match object.remove("id") {
+                Some(Value::String(id)) => id,
+                _ => String::new(),
+            };
+            calls.push(ToolCall {
+                id,
+                name,
+                arguments,
+            });
+        }
+        _ => {}
+    }
+}
+
+fn is_json(text: &str) -> bool {
+    serde_json::Deserializer::from_str(text)
+        .into_iter::<Value>()
+        .all(|value| value.is_ok())
+}
+
+// Length of the JSON object or array `text` starts with, once its closing bracket arrived.
+fn balanced_json_len(text: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (pos, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' | '[' => depth += 1,
+            '}' | ']' => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    return Some(pos + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEEPSEEK_CALLS: &str = "<｜tool▁calls▁begin｜><｜tool▁call▁begin｜>function<｜tool▁sep｜>get_weather\n```json\n{\"city\": \"Paris\"}\n```<｜tool▁call▁end｜><｜tool▁call▁begin｜>function<｜tool▁sep｜>get_time\n```json\n{}\n```<｜tool▁call▁end｜><｜tool▁calls▁end｜>";
+
+    // Streams `text` in chunks of `size` chars, so markers end up split across pushes.
+    fn stream(format: ToolCallFormat, text: &str, size: usize) -> (Vec<ToolCall>, String) {
+        let chars: Vec<char> = text.chars().collect();
+        let mut parser = ToolCallParser::new(format);
+        let mut calls: Vec<ToolCall> = chars
+            .chunks(size)
+            .flat_map(|chunk| parser.push(&chunk.iter().collect::<String>()))
+            .collect();
+        calls.extend(parser.finish());
+        (calls, parser.content().to_owned())
+    }
+
+    fn call(id: &str, name: &str, arguments: Value) -> ToolCall {
+        ToolCall {
+            id: id.to_owned(),
+            name: name.to_owned(),
+            arguments,
+        }
+    }
+
+    #[test]
+    fn parses_parallel_hermes_calls() {
+        let text = "Sure.<tool_call>{\"name\": \"a\", \"arguments\": {\"x\": 1}}</tool_call>\n<tool_call>{\"name\": \"b\", \"arguments\": {}}</tool_call>";
+        for size in [1, 3, 7, text.len()] {
+            assert_eq!(
+                stream(ToolCallFormat::Hermes, text, size),
+                (
+                    vec![
+                        call("call_0", "a", json!({"x": 1})),
+                        call("call_1", "b", json!({}))
+                    ],
+                    "Sure.\n".to_owned()
+                ),
+                "chunk size {}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn holds_back_a_partial_marker() {
+        let mut parser = ToolCallParser::new(ToolCallFormat::Hermes);
+        assert!(parser.push("text <tool").is_empty());
+        assert_eq!(parser.content(), "text ");
+        assert_eq!(
+            parser.push("_call>{\"name\": \"a\"}</tool_call>"),
+            [call("call_0", "a", json!({}))]
+        );
+        assert!(parser.finish().is_empty());
+        assert_eq!(parser.content(), "text ");
+    }
+
+    #[test]
+    fn releases_a_partial_marker_that_does_not_complete() {
+        let mut parser = ToolCallParser::new(ToolCallFormat::Hermes);
+        assert!(parser.push("see <to").is_empty());
+        assert!(parser.push("day").is_empty());
+        assert!(parser.finish().is_empty());
+        assert_eq!(parser.content(), "see <today");
+    }
+
+    #[test]
+    fn parses_an_unterminated_block_at_the_end() {
+        assert_eq!(
+            parse_tool_calls(
+                "<tool_call>{\"name\": \"a\", \"arguments\": {}}",
+                ToolCallFormat::Hermes
+            ),
+            [call("call_0", "a", json!({}))]
+        );
+    }
+
+    #[test]
+    fn parses_llama3_calls() {
+        let text =
+            "<|python_tag|>{\"name\": \"search\", \"parameters\": {\"q\": \"rust\"}}<|eom_id|>";
+        for size in [1, 4, text.len()] {
+            assert_eq!(
+                stream(ToolCallFormat::Llama3, text, size),
+                (
+                    vec![call("call_0", "search", json!({"q": "rust"}))],
+                    String::new()
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_bare_json_replies_only_as_a_whole() {
+        assert_eq!(
+            parse_tool_calls(
+                " {\"name\": \"search\", \"parameters\": {}} ",
+                ToolCallFormat::Llama3
+            ),
+            [call("call_0", "search", json!({}))]
+        );
+        assert!(parse_tool_calls(
+            "Call it like {\"name\": \"search\"} next time.",
+            ToolCallFormat::Llama3
+        )
+        .is_empty());
+        assert!(parse_tool_calls("{\"name\": \"search\"}", ToolCallFormat::Hermes).is_empty());
+    }
+
+    #[test]
+    fn parses_parallel_deepseek_calls() {
+        for size in [1, 5, DEEPSEEK_CALLS.len()] {
+            assert_eq!(
+                stream(ToolCallFormat::DeepSeek, DEEPSEEK_CALLS, size),
+                (
+                    vec![
+                        call("call_0", "get_weather", json!({"city": "Paris"})),
+                        call("call_1", "get_time", json!({})),
+                    ],
+                    String::new()
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn parses_deepseek_v3_1_calls() {
+        assert_eq!(
+            parse_tool_calls(
+                "<｜tool▁call▁begin｜>get<｜tool▁sep｜>{\"a\": 1}<｜tool▁call▁end｜>",
+                ToolCallFormat::DeepSeek
+            ),
+            [call("call_0", "get", json!({"a": 1}))]
+        );
+    }
+
+    #[test]
+    fn parses_json_calls_in_prose() {
+        let text = "Calling [{\"name\": \"a\", \"arguments\": \"{\\\"x\\\": 1}\"}, {\"type\": \"function\", \"id\": \"c9\", \"function\": {\"name\": \"b\", \"arguments\": {}}}] now {\"not\": \"a call\"}";
+        assert_eq!(
+            stream(ToolCallFormat::Json, text, 6),
+            (
+                vec![
+                    call("call_0", "a", json!({"x": 1})),
+                    call("c9", "b", json!({}))
+                ],
+                "Calling  now {\"not\": \"a call\"}".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn auto_detects_every_format() {
+        for text in [
+            "<tool_call>{\"name\": \"a\"}</tool_call>",
+            "<|python_tag|>{\"name\": \"a\"}",
+            "<｜tool▁call▁begin｜>a<｜tool▁sep｜>{}<｜tool▁call▁end｜>",
+            "{\"name\": \"a\"}",
+        ] {
+            assert_eq!(
+                parse_tool_calls(text, ToolCallFormat::Auto),
+                [call("call_0", "a", json!({}))],
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn skips_calls_inside_reasoning() {
+        assert_eq!(
+            stream(
+                ToolCallFormat::Hermes,
+                "<think>maybe <tool_call>{\"name\": \"no\"}</tool_call></think>Answer",
+                3
+            ),
+            (Vec::new(), "Answer".to_owned())
+        );
+    }
+
+    #[test]
+    fn maps_template_names_to_formats() {
+        assert_eq!(ToolCallFormat::by_name("Qwen3"), ToolCallFormat::Hermes);
+        assert_eq!(ToolCallFormat::by_name("chatml"), ToolCallFormat::Hermes);
+        assert_eq!(
+            ToolCallFormat::by_name("deepseek_r1"),
+            ToolCallFormat::DeepSeek
+        );
+        assert_eq!(ToolCallFormat::by_name("llama3"), ToolCallFormat::Llama3);
+        assert_eq!(ToolCallFormat::by_name("gemma3"), ToolCallFormat::Auto);
+    }
+}
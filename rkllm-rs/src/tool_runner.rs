@@ -0,0 +1,466 @@
+use crate::prelude::{
+    parse_tool_calls, validate_tool_call, ChatSession, RkllmError, ToolCall, ToolCallFormat,
+    ToolSpec,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+pub const DEFAULT_MAX_TOOL_ITERATIONS: usize = 5;
+pub const DEFAULT_TOOL_CALL_RETRIES: usize = 2;
+
+pub type ToolHandler<'a> = Box<dyn FnMut(Value) -> Result<Value, RkllmError> + Send + 'a>;
+
+// How calls are checked against the tools passed to `LLMHandle::set_function_tools`, or the
+// runner's own specs when the handle has none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolValidation {
+    Off,
+    // The run fails with `RkllmError::InvalidToolCall`.
+    Error,
+    // The problems are sent back as the call's `Tool` result so the model can fix the call.
+    Reprompt { max_retries: usize },
+}
+
+impl Default for ToolValidation {
+    fn default() -> Self {
+        ToolValidation::Reprompt {
+            max_retries: DEFAULT_TOOL_CALL_RETRIES,
+        }
+    }
+}
+
+// What happens when a handler returns an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToolErrorHandling {
+    // The run fails with the handler's error; the session is left after the model's call.
+    #[default]
+    Abort,
+    // The error is sent back as the call's `Tool` result and the run goes on.
+    Report,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolOutcome {
+    // The handler ran (or the tool was unknown) and `result` is what it returned.
+    Completed,
+    // Validation failed and the handler did not run; `result` holds the problems.
+    Rejected,
+    // The handler returned an error that was reported to the model.
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolInvocation {
+    pub call: ToolCall,
+    pub result: Value,
+    pub outcome: ToolOutcome,
+}
+
+#[derive(Debug, Clone)]
+pub struct ToolRun {
+    pub answer: String,
+    // Every call in the order the model made it, including rejected and failed ones.
+    pub invocations: Vec<ToolInvocation>,
+    // Model replies that contained tool calls.
+    pub iterations: usize,
+}
+
+// Drives a `ChatSession` through tool calls: every reply is scanned for calls, the registered
+// handlers run and their results go back as one `Tool` turn, until the model answers without
+// calling a tool. The tools themselves are announced with `LLMHandle::set_function_tools`.
+pub struct ToolRunner<'a> {
+    handlers: HashMap<String, ToolHandler<'a>>,
+    specs: Vec<ToolSpec>,
+    format: ToolCallFormat,
+    validation: ToolValidation,
+    error_handling: ToolErrorHandling,
+    max_iterations: usize,
+}
+
+impl Default for ToolRunner<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> ToolRunner<'a> {
+    pub fn new() -> Self {
+        Self {
+            handlers: HashMap::new(),
+            specs: Vec::new(),
+            format: ToolCallFormat::default(),
+            validation: ToolValidation::default(),
+            error_handling: ToolErrorHandling::default(),
+            max_iterations: DEFAULT_MAX_TOOL_ITERATIONS,
+        }
+    }
+
+    pub fn with_tool(
+        mut self,
+        name: impl Into<String>,
+        handler: impl FnMut(Value) -> Result<Value, RkllmError> + Send + 'a,
+    ) -> Self {
+        self.register(name, handler);
+        self
+    }
+
+    pub fn with_spec(
+        mut self,
+        spec: ToolSpec,
+        handler: impl FnMut(Value) -> Result<Value, RkllmError> + Send + 'a,
+    ) -> Self {
+        self.register_spec(spec, handler);
+        self
+    }
+
+    // Registers the handler together with a spec derived from its argument type. Arguments that
+    // do not deserialize into `A` are reported back to the model as an error result.
+    #[cfg(feature = "tool_schema")]
+    pub fn with_typed_tool<A, R>(
+        mut self,
+        name: impl Into<String>,
+        description: impl Into<String>,
+        mut handler: impl FnMut(A) -> Result<R, RkllmError> + Send + 'a,
+    ) -> Self
+    where
+        A: serde::de::DeserializeOwned + schemars::JsonSchema,
+        R: serde::Serialize,
+    {
+        let spec = ToolSpec::for_args::<A>(name, description);
+        self.register_spec(spec, move |arguments| {
+            let arguments = match serde_json::from_value(arguments) {
+                Ok(arguments) => arguments,
+                Err(err) => return Ok(json!({ "error": format!("invalid arguments: {}", err) })),
+            };
+            Ok(serde_json::to_value(handler(arguments)?)?)
+        });
+        self
+    }
+
+    // E.g. `ToolCallFormat::for_template(&ChatTemplate::LLAMA3)`; defaults to `Auto`.
+    pub fn with_format(mut self, format: ToolCallFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_validation(mut self, validation: ToolValidation) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    pub fn with_error_handling(mut self, error_handling: ToolErrorHandling) -> Self {
+        self.error_handling = error_handling;
+        self
+    }
+
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        handler: impl FnMut(Value) -> Result<Value, RkllmError> + Send + 'a,
+    ) {
+        self.handlers.insert(name.into(), Box::new(handler));
+    }
+
+    pub fn register_spec(
+        &mut self,
+        spec: ToolSpec,
+        handler: impl FnMut(Value) -> Result<Value, RkllmError> + Send + 'a,
+    ) {
+        self.register(spec.name(), handler);
+        self.specs
+            .retain(|registered| registered.name() != spec.name());
+        self.specs.push(spec);
+    }
+
+    pub fn unregister(&mut self, name: &str) -> bool {
+        self.specs.retain(|registered| registered.name() != name);
+        self.handlers.remove(name).is_some()
+    }
+
+    // Specs of the tools registered with one, in registration order; pass them to
+    // `LLMHandle::set_function_tools`.
+    pub fn specs(&self) -> &[ToolSpec] {
+        &self.specs
+    }
+
+    pub fn tool_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.handlers.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn format(&self) -> ToolCallFormat {
+        self.format
+    }
+
+    pub fn validation(&self) -> ToolValidation {
+        self.validation
+    }
+
+    pub fn error_handling(&self) -> ToolErrorHandling {
+        self.error_handling
+    }
+
+    pub fn max_iterations(&self) -> usize {
+        self.max_iterations
+    }
+
+    pub fn run(
+        &mut self,
+        session: &mut ChatSession<'_>,
+        prompt: impl Into<String>,
+    ) -> Result<ToolRun, RkllmError> {
+        let reply = session.send(prompt)?;
+        self.resume(session, reply)
+    }
+
+    // Continues from a reply that was already received, e.g. one returned by `ChatSession::send`.
+    pub fn resume(
+        &mut self,
+        session: &mut ChatSession<'_>,
+        mut reply: String,
+    ) -> Result<ToolRun, RkllmError> {
+        let tools = match self.validation {
+            ToolValidation::Off => None,
+            _ => session.handle().function_tools().or_else(|| {
+                serde_json::to_value(&self.specs)
+                    .ok()
+                    .filter(|_| !self.specs.is_empty())
+            }),
+        };
+        let mut invocations = Vec::new();
+        let mut iterations = 0;
+        let mut retries = 0;
+        loop {
+            let calls = parse_tool_calls(&reply, self.format);
+            if calls.is_empty() {
+                return Ok(ToolRun {
+                    answer: reply,
+                    invocations,
+                    iterations,
+                });
+            }
+            if iterations == self.max_iterations {
+                return Err(RkllmError::ToolIterationsExceeded {
+                    max_iterations: self.max_iterations,
+                });
+            }
+            iterations += 1;
+
+            let answered = self.answer_calls(tools.as_ref(), calls, retries)?;
+            if answered
+                .iter()
+                .any(|invocation| invocation.outcome == ToolOutcome::Rejected)
+            {
+                retries += 1;
+            }
+            let results: Vec<String> = answered
+                .iter()
+                .map(|invocation| invocation.result.to_string())
+                .collect();
+            invocations.extend(answered);
+            reply = session.send_tool_result(results.join("\n"))?;
+        }
+    }
+
+    // Validates and runs the calls of one reply; `retries` counts the earlier replies with
+    // rejected calls.
+    fn answer_calls(
+        &mut self,
+        tools: Option<&Value>,
+        calls: Vec<ToolCall>,
+        retries: usize,
+    ) -> Result<Vec<ToolInvocation>, RkllmError> {
+        let mut invocations = Vec::with_capacity(calls.len());
+        for call in calls {
+            let checked = match tools {
+                Some(tools) => validate_tool_call(tools, &call),
+                None => Ok(()),
+            };
+            let (result, outcome) = match (checked, self.validation) {
+                (Ok(()), _) => match (self.invoke(&call), self.error_handling) {
+                    (Ok(result), _) => (result, ToolOutcome::Completed),
+                    (Err(err), ToolErrorHandling::Report) => {
+                        (json!({ "error": err.to_string() }), ToolOutcome::Failed)
+                    }
+                    (Err(err), ToolErrorHandling::Abort) => return Err(err),
+                },
+                (Err(err), ToolValidation::Reprompt { max_retries }) if retries < max_retries => (
+                    json!({
+                        "error": format!("{}. Call the tool again with corrected arguments.", err)
+                    }),
+                    ToolOutcome::Rejected,
+                ),
+                (Err(err), _) => return Err(err),
+            };
+            invocations.push(ToolInvocation {
+                call,
+                result,
+                outcome,
+            });
+        }
+        Ok(invocations)
+    }
+
+    pub fn invoke(&mut self, call: &ToolCall) -> Result<Value, RkllmError> {
+        match self.handlers.get_mut(&call.name) {
+            Some(handler) => handler(call.arguments.clone()),
+            // Reported to the model so it can correct itself instead of failing the run.
+            None => Ok(json!({ "error": format!("unknown tool {}", call.name) })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::{LLMConfig, LLMHandle};
+
+    fn call(name: &str, arguments: Value) -> ToolCall {
+        ToolCall {
+            id: String::new(),
+            name: name.to_owned(),
+            arguments,
+        }
+    }
+
+    fn add_runner<'a>() -> ToolRunner<'a> {
+        let spec = ToolSpec::function(
+            "add",
+            "Adds two numbers",
+            json!({
+                "type": "object",
+                "properties": { "a": { "type": "number" }, "b": { "type": "number" } },
+                "required": ["a", "b"]
+            }),
+        );
+        ToolRunner::new()
+            .with_spec(spec, |arguments| {
+                Ok(json!(
+                    arguments["a"].as_f64().unwrap_or_default()
+                        + arguments["b"].as_f64().unwrap_or_default()
+                ))
+            })
+            .with_tool("fail", |_| {
+                Err(RkllmError::InvalidArgument("boom".to_owned()))
+            })
+    }
+
+    fn tools(runner: &ToolRunner<'_>) -> Value {
+        serde_json::to_value(runner.specs()).unwrap()
+    }
+
+    #[test]
+    fn completed_calls_carry_the_handler_result() {
+        let mut runner = add_runner();
+        let tools = tools(&runner);
+        let answered = runner
+            .answer_calls(
+                Some(&tools),
+                vec![call("add", json!({ "a": 1, "b": 2 }))],
+                0,
+            )
+            .unwrap();
+        assert_eq!(answered.len(), 1);
+        assert_eq!(answered[0].outcome, ToolOutcome::Completed);
+        assert_eq!(answered[0].result, json!(3.0));
+    }
+
+    #[test]
+    fn invalid_calls_are_marked_rejected_while_retries_remain() {
+        let mut runner = add_runner();
+        let tools = tools(&runner);
+        let calls = vec![
+            call("add", json!({ "a": 1 })),
+            call("add", json!({ "a": 1, "b": 1 })),
+        ];
+        let answered = runner.answer_calls(Some(&tools), calls.clone(), 0).unwrap();
+        assert_eq!(answered[0].outcome, ToolOutcome::Rejected);
+        assert!(answered[0].result["error"].as_str().unwrap().contains("b"));
+        assert_eq!(answered[1].outcome, ToolOutcome::Completed);
+
+        let exhausted = runner.answer_calls(Some(&tools), calls, DEFAULT_TOOL_CALL_RETRIES);
+        assert!(matches!(exhausted, Err(RkllmError::InvalidToolCall { .. })));
+    }
+
+    #[test]
+    fn validation_modes() {
+        let mut runner = add_runner().with_validation(ToolValidation::Error);
+        let tools = tools(&runner);
+        let invalid = vec![call("add", json!({ "a": "one", "b": 2 }))];
+        assert!(matches!(
+            runner.answer_calls(Some(&tools), invalid.clone(), 0),
+            Err(RkllmError::InvalidToolCall { .. })
+        ));
+
+        let mut runner = runner.with_validation(ToolValidation::Off);
+        let answered = runner.answer_calls(None, invalid, 0).unwrap();
+        assert_eq!(answered[0].outcome, ToolOutcome::Completed);
+    }
+
+    #[test]
+    fn handler_errors_abort_or_are_reported() {
+        let mut runner = add_runner();
+        let calls = vec![
+            call("fail", json!({})),
+            call("add", json!({ "a": 1, "b": 1 })),
+        ];
+        assert!(matches!(
+            runner.answer_calls(None, calls.clone(), 0),
+            Err(RkllmError::InvalidArgument(message)) if message == "boom"
+        ));
+
+        let mut runner = runner.with_error_handling(ToolErrorHandling::Report);
+        let answered = runner.answer_calls(None, calls, 0).unwrap();
+        assert_eq!(answered[0].outcome, ToolOutcome::Failed);
+        assert!(answered[0].result["error"]
+            .as_str()
+            .unwrap()
+            .contains("boom"));
+        assert_eq!(answered[1].outcome, ToolOutcome::Completed);
+    }
+
+    #[test]
+    fn unknown_tools_are_reported_without_validation() {
+        let mut runner = add_runner();
+        let answered = runner
+            .answer_calls(None, vec![call("missing", json!({}))], 0)
+            .unwrap();
+        assert_eq!(answered[0].outcome, ToolOutcome::Completed);
+        assert_eq!(
+            answered[0].result,
+            json!({ "error": "unknown tool missing" })
+        );
+    }
+
+    #[test]
+    fn resume_returns_replies_without_calls() {
+        let handle = LLMHandle::detached(LLMConfig::default(), None);
+        let mut session = ChatSession::new(&handle);
+        let run = add_runner()
+            .resume(&mut session, "The answer is 3.".to_owned())
+            .unwrap();
+        assert_eq!(run.answer, "The answer is 3.");
+        assert!(run.invocations.is_empty());
+        assert_eq!(run.iterations, 0);
+    }
+
+    #[test]
+    fn resume_stops_after_max_iterations() {
+        let handle = LLMHandle::detached(LLMConfig::default(), None);
+        let mut session = ChatSession::new(&handle);
+        let reply = r#"<tool_call>{"name": "add", "arguments": {"a": 1, "b": 2}}</tool_call>"#;
+        let result = add_runner()
+            .with_max_iterations(0)
+            .resume(&mut session, reply.to_owned());
+        assert!(matches!(
+            result,
+            Err(RkllmError::ToolIterationsExceeded { max_iterations: 0 })
+        ));
+    }
+}
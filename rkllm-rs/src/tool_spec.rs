@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionSpec {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    // JSON Schema of the arguments object.
+    pub parameters: Value,
+}
+
+// One entry of the tools list passed to `LLMHandle::set_function_tools`, in the OpenAI
+// function calling layout chat templates expect.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolSpec {
+    #[serde(rename = "type")]
+    pub tool_type: String,
+    pub function: FunctionSpec,
+}
+
+impl ToolSpec {
+    pub fn function(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        parameters: Value,
+    ) -> Self {
+        Self {
+            tool_type: "function".to_owned(),
+            function: FunctionSpec {
+                name: name.into(),
+                description: description.into(),
+                parameters,
+            },
+        }
+    }
+
+    // For tools without arguments.
+    pub fn without_parameters(name: impl Into<String>, description: impl Into<String>) -> Self {
+        Self::function(
+            name,
+            description,
+            json!({ "type": "object", "properties": {} }),
+        )
+    }
+
+    // The schema is derived from the argument type, so it follows the struct the handler
+    // deserializes into. Doc comments on the fields become the property descriptions.
+    #[cfg(feature = "tool_schema")]
+    pub fn for_args<A: schemars::JsonSchema>(
+        name: impl Into<String>,
+        description: impl Into<String>,
+    ) -> Self {
+        Self::function(name, description, parameters_schema::<A>())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.function.name
+    }
+}
+
+// Self-contained draft-07 schema of `A`: small models follow inline schemas better than `$ref`s,
+// and the root title and `$schema` are noise in the prompt.
+#[cfg(feature = "tool_schema")]
+pub fn parameters_schema<A: schemars::JsonSchema>() -> Value {
+    let mut schema = schemars::generate::SchemaSettings::draft07()
+        .with(|settings| {
+            settings.inline_subschemas = true;
+            settings.meta_schema = None;
+        })
+        .for_deserialize()
+        .into_generator()
+        .into_root_schema_for::<A>();
+    schema.remove("title");
+    schema.to_value()
+}
+
+#[cfg(all(test, feature = "tool_schema"))]
+mod tests {
+    use super::*;
+    use crate::prelude::{validate_arguments, validate_tool_call, ToolCall};
+
+    #[derive(Deserialize, schemars::JsonSchema)]
+    #[allow(dead_code)]
+    enum Unit {
+        Celsius,
+        Fahrenheit,
+    }
+
+    #[derive(Deserialize, schemars::JsonSchema)]
+    #[allow(dead_code)]
+    struct Location {
+        city: String,
+        country: Option<String>,
+    }
+
+    #[derive(Deserialize, schemars::JsonSchema)]
+    #[allow(dead_code)]
+    struct WeatherArgs {
+        /// Where to look up the weather.
+        location: Location,
+        unit: Unit,
+        days: Vec<u8>,
+    }
+
+    #[test]
+    fn derived_schema_is_inlined_draft07() {
+        let schema = parameters_schema::<WeatherArgs>();
+        let text = schema.to_string();
+        assert!(!text.contains("$ref"), "{}", text);
+        assert!(schema.get("definitions").is_none());
+        assert!(schema.get("$schema").is_none());
+        assert!(schema.get("title").is_none());
+        assert_eq!(schema["type"], "object");
+        assert_eq!(
+            schema["properties"]["location"]["description"],
+            "Where to look up the weather."
+        );
+    }
+
+    #[test]
+    fn derived_schema_round_trips_through_validation() {
+        let spec = ToolSpec::for_args::<WeatherArgs>("get_weather", "Weather forecast");
+        let schema = &spec.function.parameters;
+
+        let valid = json!({
+            "location": { "city": "Taipei", "country": null },
+            "unit": "Celsius",
+            "days": [1, 2]
+        });
+        assert_eq!(validate_arguments(schema, &valid), Vec::<String>::new());
+        assert!(serde_json::from_value::<WeatherArgs>(valid.clone()).is_ok());
+
+        let tools = serde_json::to_value(vec![spec.clone()]).unwrap();
+        let call = ToolCall {
+            id: String::new(),
+            name: "get_weather".to_owned(),
+            arguments: valid,
+        };
+        assert!(validate_tool_call(&tools, &call).is_ok());
+
+        for invalid in [
+            json!({ "location": { "city": "Taipei" }, "unit": "Kelvin", "days": [] }),
+            json!({ "location": {}, "unit": "Celsius", "days": [] }),
+            json!({ "location": { "city": "Taipei" }, "unit": "Celsius", "days": ["today"] }),
+            json!({ "location": { "city": "Taipei" }, "unit": "Celsius" }),
+        ] {
+            assert!(
+                !validate_arguments(schema, &invalid).is_empty(),
+                "{} was accepted",
+                invalid
+            );
+            assert!(serde_json::from_value::<WeatherArgs>(invalid).is_err());
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use crate::prelude::{LLMHandle, RkllmError, ToolCall};
+use serde_json::Value;
+
+impl LLMHandle {
+    // Checks a call against the tools last passed to `set_function_tools`; without them any call
+    // is accepted.
+    pub fn validate_tool_call(&self, call: &ToolCall) -> Result<(), RkllmError> {
+        match self.function_tools() {
+            Some(tools) => validate_tool_call(&tools, call),
+            None => Ok(()),
+        }
+    }
+}
+
+// `tools` is a tools list as passed to `set_function_tools`, with entries in the
+// `{"type": "function", "function": {...}}` layout or bare `{"name": ..., "parameters": ...}`.
+pub fn validate_tool_call(tools: &Value, call: &ToolCall) -> Result<(), RkllmError> {
+    let Some(parameters) = tool_parameters(tools, &call.name) else {
+        return Err(RkllmError::InvalidToolCall {
+            name: call.name.clone(),
+            problems: vec![format!("unknown tool {}", call.name)],
+        });
+    };
+    let problems = validate_arguments(parameters, &call.arguments);
+    if problems.is_empty() {
+        return Ok(());
+    }
+    Err(RkllmError::InvalidToolCall {
+        name: call.name.clone(),
+        problems,
+    })
+}
+
+// Checks `type`, `enum`, `const`, `required`, `properties`, `additionalProperties`, `items`,
+// `anyOf`, `oneOf` (exactly one branch must match) and `allOf`; other keywords are not
+// enforced. Returns one message per problem.
+pub fn validate_arguments(schema: &Value, arguments: &Value) -> Vec<String> {
+    let mut problems = Vec::new();
+    check(schema, arguments, "arguments", &mut problems);
+    problems
+}
+
+fn tool_parameters<'a>(tools: &'a Value, name: &str) -> Option<&'a Value> {
+    let tools = match tools {
+        Value::Array(tools) => tools.as_slice(),
+        tool => std::slice::from_ref(tool),
+    };
+    tools.iter().find_map(|tool| {
+        let function = tool.get("function").unwrap_or(tool);
+        if function.get("name")?.as_str()? != name {
+            return None;
+        }
+        Some(function.get("parameters").unwrap_or(&Value::Null))
+    })
+}
+
+fn check(schema: &Value, value: &Value, path: &str, problems: &mut Vec<String>) {
+    let Value::Object(schema) = schema else {
+        return;
+    };
+
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            let allowed: Vec<String> = allowed.iter().map(Value::to_string).collect();
+            problems.push(format!("{} must be one of {}", path, allowed.join(", ")));
+            return;
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            problems.push(format!("{} must be {}", path, expected));
+            return;
+        }
+    }
+    if let Some(types) = schema.get("type") {
+        let types: Vec<&str> = match types {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|name| has_type(value, name)) {
+            problems.push(format!(
+                "{} must be of type {} but is {}",
+                path,
+                types.join(" or "),
+                type_name(value)
+            ));
+            return;
+        }
+    }
+
+    if let Some(Value::Array(branches)) = schema.get("allOf") {
+        for branch in branches {
+            check(branch, value, path, problems);
+        }
+    }
+    if let Some(Value::Array(branches)) = schema.get("anyOf") {
+        if !branches.is_empty() && matching_branches(branches, value, path) == 0 {
+            problems.push(format!("{} does not match any allowed schema", path));
+        }
+    }
+    if let Some(Value::Array(branches)) = schema.get("oneOf") {
+        match matching_branches(branches, value, path) {
+            0 if !branches.is_empty() => {
+                problems.push(format!("{} does not match any allowed schema", path))
+            }
+            0 | 1 => {}
+            matched => problems.push(format!(
+                "{} must match exactly one allowed schema but matches {}",
+                path, matched
+            )),
+        }
+    }
+
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for name in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(name) {
+                        problems.push(format!("missing required property {}", join(path, name)));
+                    }
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (name, property) in object {
+                match properties.and_then(|properties| properties.get(name)) {
+                    Some(property_schema) => {
+                        check(property_schema, property, &join(path, name), problems)
+                    }
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            problems.push(format!("unexpected property {}", join(path, name)))
+                        }
+                        Some(additional) => {
+                            check(additional, property, &join(path, name), problems)
+                        }
+                        None => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    check(item_schema, item, &format!("{}[{}]", path, index), problems);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn matching_branches(branches: &[Value], value: &Value, path: &str) -> usize {
+    branches
+        .iter()
+        .filter(|branch| {
+            let mut branch_problems = Vec::new();
+            check(branch, value, path, &mut branch_problems);
+            branch_problems.is_empty()
+        })
+        .count()
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path == "arguments" {
+        name.to_owned()
+    } else {
+        format!("{}.{}", path, name)
+    }
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|n| n.fract() == 0.0)
+        }
+        // Unknown type names are not enforced.
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn weather_schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "city": {"type": "string"},
+                "days": {"type": "integer"},
+                "unit": {"enum": ["celsius", "fahrenheit"]},
+            },
+            "required": ["city"],
+            "additionalProperties": false,
+        })
+    }
+
+    fn call(name: &str, arguments: Value) -> ToolCall {
+        ToolCall {
+            id: "call_0".to_owned(),
+            name: name.to_owned(),
+            arguments,
+        }
+    }
+
+    #[test]
+    fn accepts_valid_arguments() {
+        let arguments = json!({"city": "Paris", "days": 2.0, "unit": "celsius"});
+        assert!(validate_arguments(&weather_schema(), &arguments).is_empty());
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let arguments = json!({"days": "3", "unit": "kelvin", "extra": true});
+        assert_eq!(
+            validate_arguments(&weather_schema(), &arguments),
+            [
+                "missing required property city",
+                "days must be of type integer but is string",
+                "unexpected property extra",
+                "unit must be one of \"celsius\", \"fahrenheit\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_nested_paths() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "items": {
+                    "type": "array",
+                    "items": {"type": "object", "properties": {"name": {"type": "string"}}},
+                },
+            },
+        });
+        let arguments = json!({"items": [{"name": "a"}, {"name": 1}]});
+        assert_eq!(
+            validate_arguments(&schema, &arguments),
+            ["items[1].name must be of type string but is number"]
+        );
+    }
+
+    #[test]
+    fn checks_additional_property_schemas_and_const() {
+        let schema = json!({
+            "properties": {"version": {"const": 2}},
+            "additionalProperties": {"type": "number"},
+        });
+        assert_eq!(
+            validate_arguments(&schema, &json!({"version": 1, "ratio": "high"})),
+            [
+                "ratio must be of type number but is string",
+                "version must be 2"
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_unions() {
+        let schema = json!({"anyOf": [{"type": "integer"}, {"type": "null"}]});
+        assert!(validate_arguments(&schema, &json!(null)).is_empty());
+        assert!(validate_arguments(&schema, &json!(3)).is_empty());
+        assert_eq!(
+            validate_arguments(&schema, &json!("3")),
+            ["arguments does not match any allowed schema"]
+        );
+        let schema = json!({"allOf": [{"type": "number"}, {"enum": [1, 2]}]});
+        assert_eq!(
+            validate_arguments(&schema, &json!(3)),
+            ["arguments must be one of 1, 2"]
+        );
+    }
+
+    #[test]
+    fn one_of_needs_exactly_one_match() {
+        let schema = json!({"oneOf": [{"type": "integer"}, {"type": "number"}]});
+        assert!(validate_arguments(&schema, &json!(1.5)).is_empty());
+        assert_eq!(
+            validate_arguments(&schema, &json!(2)),
+            ["arguments must match exactly one allowed schema but matches 2"]
+        );
+        assert_eq!(
+            validate_arguments(&schema, &json!("2")),
+            ["arguments does not match any allowed schema"]
+        );
+    }
+
+    #[test]
+    fn validates_calls_against_both_tool_layouts() {
+        let tools = json!([
+            {"type": "function", "function": {"name": "weather", "parameters": weather_schema()}},
+            {"name": "time", "parameters": {"type": "object", "required": ["zone"]}},
+        ]);
+        assert!(validate_tool_call(&tools, &call("weather", json!({"city": "Oslo"}))).is_ok());
+        assert!(validate_tool_call(&tools, &call("time", json!({"zone": "UTC"}))).is_ok());
+        assert!(matches!(
+            validate_tool_call(&tools, &call("time", json!({}))),
+            Err(RkllmError::InvalidToolCall { name, problems })
+                if name == "time" && problems == ["missing required property zone"]
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_tools() {
+        let tools = json!([{"name": "time"}]);
+        assert!(matches!(
+            validate_tool_call(&tools, &call("weather", json!({}))),
+            Err(RkllmError::InvalidToolCall { name, problems })
+                if name == "weather" && problems == ["unknown tool weather"]
+        ));
+        // A tool without parameters takes any arguments.
+        assert!(validate_tool_call(&tools, &call("time", json!({"zone": 1}))).is_ok());
+    }
+}
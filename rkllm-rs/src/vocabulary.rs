@@ -0,0 +1,382 @@
+use crate::prelude::{LLMHandle, RkllmError};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const TOKENIZER_FILE: &str = "tokenizer.json";
+
+// End-of-turn tokens of the common model families, treated as EOS when loading a tokenizer.
+const DEFAULT_EOS_TOKENS: [&str; 9] = [
+    "</s>",
+    "<eos>",
+    "<end_of_turn>",
+    "<|endoftext|>",
+    "<|im_end|>",
+    "<|eot_id|>",
+    "<|end_of_text|>",
+    "<|end|>",
+    "<｜end▁of▁sentence｜>",
+];
+
+// Maps the model's token ids to the bytes they decode to, for Rust-side sampling.
+pub trait Vocabulary: fmt::Debug + Send + Sync {
+    fn vocab_size(&self) -> usize;
+
+    // Empty for special and unknown tokens, which constraints never allow.
+    fn token_bytes(&self, token_id: i32) -> &[u8];
+
+    fn eos_token_ids(&self) -> &[i32];
+
+    fn is_eos(&self, token_id: i32) -> bool {
+        self.eos_token_ids().contains(&token_id)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TokenVocabulary {
+    tokens: Vec<Vec<u8>>,
+    eos_token_ids: Vec<i32>,
+}
+
+impl TokenVocabulary {
+    pub fn new(tokens: Vec<Vec<u8>>, eos_token_ids: Vec<i32>) -> Self {
+        Self {
+            tokens,
+            eos_token_ids,
+        }
+    }
+
+    // Accepts a model directory or a Hugging Face `tokenizer.json`.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, RkllmError> {
+        let path = path.as_ref();
+        let path = if path.is_dir() {
+            path.join(TOKENIZER_FILE)
+        } else {
+            path.to_path_buf()
+        };
+        let json = fs::read_to_string(&path).map_err(|err| RkllmError::io(&path, err))?;
+        Self::from_tokenizer_json(&json)
+    }
+
+    // Byte-level BPE (GPT-2, Qwen, Llama 3) and SentencePiece (`▁`, `<0x0A>`) vocabularies are
+    // decoded to raw bytes. Special added tokens decode to nothing.
+    pub fn from_tokenizer_json(json: &str) -> Result<Self, RkllmError> {
+        let tokenizer: Value = serde_json::from_str(json)?;
+        let byte_level =
+            uses_byte_level(&tokenizer["decoder"]) || uses_byte_level(&tokenizer["pre_tokenizer"]);
+        let byte_decoder = byte_level.then(byte_decoder);
+
+        let mut tokens: HashMap<usize, Vec<u8>> = HashMap::new();
+        match &tokenizer["model"]["vocab"] {
+            Value::Object(vocab) => {
+                for (token, id) in vocab {
+                    if let Some(id) = id.as_u64() {
+                        tokens.insert(id as usize, decode_token(token, byte_decoder.as_ref()));
+                    }
+                }
+            }
+            // Unigram models list `[token, score]` pairs in id order.
+            Value::Array(vocab) => {
+                for (id, entry) in vocab.iter().enumerate() {
+                    if let Some(token) = entry.get(0).and_then(Value::as_str) {
+                        tokens.insert(id, decode_token(token, byte_decoder.as_ref()));
+                    }
+                }
+            }
+            _ => return Err(RkllmError::invalid_argument("tokenizer has no model.vocab")),
+        }
+
+        let mut eos_token_ids = Vec::new();
+        for added in tokenizer["added_tokens"].as_array().into_iter().flatten() {
+            let (Some(id), Some(content)) = (added["id"].as_u64(), added["content"].as_str())
+            else {
+                continue;
+            };
+            let special = added["special"].as_bool().unwrap_or(false);
+            tokens.insert(
+                id as usize,
+                if special {
+                    Vec::new()
+                } else {
+                    content.as_bytes().to_vec()
+                },
+            );
+            if DEFAULT_EOS_TOKENS.contains(&content) {
+                eos_token_ids.push(id as i32);
+            }
+        }
+
+        let vocab_size = tokens.keys().max().map_or(0, |max| max + 1);
+        let mut by_id = vec![Vec::new(); vocab_size];
+        for (id, bytes) in tokens {
+            by_id[id] = bytes;
+        }
+        Ok(Self::new(by_id, eos_token_ids))
+    }
+
+    // Replaces the EOS tokens guessed from the tokenizer, e.g. with `eos_token_id` from
+    // `generation_config.json`.
+    pub fn with_eos_token_ids(mut self, eos_token_ids: impl Into<Vec<i32>>) -> Self {
+        self.eos_token_ids = eos_token_ids.into();
+        self
+    }
+}
+
+impl Vocabulary for TokenVocabulary {
+    fn vocab_size(&self) -> usize {
+        self.tokens.len()
+    }
+
+    fn token_bytes(&self, token_id: i32) -> &[u8] {
+        usize::try_from(token_id)
+            .ok()
+            .and_then(|id| self.tokens.get(id))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    fn eos_token_ids(&self) -> &[i32] {
+        &self.eos_token_ids
+    }
+}
+
+static NEXT_INDEX_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    // Sorted by byte.
+    children: Vec<(u8, usize)>,
+    // Tokens whose bytes end here.
+    tokens: Vec<i32>,
+}
+
+// Byte trie over a vocabulary's non-empty, non-EOS tokens, built once per constrained run so
+// constraints can check every token sharing a prefix with a single walk of that prefix.
+#[derive(Debug)]
+pub struct TokenIndex {
+    id: u64,
+    nodes: Vec<TrieNode>,
+}
+
+impl TokenIndex {
+    pub fn new(vocabulary: &dyn Vocabulary) -> Self {
+        let mut nodes = vec![TrieNode::default()];
+        for token_id in (0..vocabulary.vocab_size()).filter_map(|id| i32::try_from(id).ok()) {
+            let bytes = vocabulary.token_bytes(token_id);
+            if bytes.is_empty() || vocabulary.is_eos(token_id) {
+                continue;
+            }
+            let mut node = 0;
+            for &byte in bytes {
+                node = match nodes[node]
+                    .children
+                    .binary_search_by_key(&byte, |&(child_byte, _)| child_byte)
+                {
+                    Ok(child) => nodes[node].children[child].1,
+                    Err(insert_at) => {
+                        let child = nodes.len();
+                        nodes.push(TrieNode::default());
+                        nodes[node].children.insert(insert_at, (byte, child));
+                        child
+                    }
+                };
+            }
+            nodes[node].tokens.push(token_id);
+        }
+        Self {
+            id: NEXT_INDEX_ID.fetch_add(1, Ordering::Relaxed),
+            nodes,
+        }
+    }
+
+    // Unique per index, so constraints can cache results for the vocabulary it was built from.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    // Walks the trie depth first from `start`: `step` maps the state after a prefix and the next
+    // byte to the state after the longer prefix, or `None` to skip every token below it.
+    // Returns the tokens whose every byte was accepted.
+    pub fn walk<S>(&self, start: S, mut step: impl FnMut(&S, u8) -> Option<S>) -> Vec<i32> {
+        let mut allowed = Vec::new();
+        let mut stack = vec![(0, start)];
+        while let Some((node, state)) = stack.pop() {
+            let node = &self.nodes[node];
+            allowed.extend_from_slice(&node.tokens);
+            for &(byte, child) in &node.children {
+                if let Some(next) = step(&state, byte) {
+                    stack.push((child, next));
+                }
+            }
+        }
+        allowed
+    }
+}
+
+impl LLMHandle {
+    // Loads the model's `tokenizer.json` (or the one in a model directory) as the handle's
+    // vocabulary.
+    pub fn load_vocabulary(&self, path: impl AsRef<Path>) -> Result<(), RkllmError> {
+        self.set_vocabulary(TokenVocabulary::from_path(path)?);
+        Ok(())
+    }
+}
+
+fn uses_byte_level(component: &Value) -> bool {
+    component["type"] == "ByteLevel"
+        || ["decoders", "pretokenizers"].iter().any(|key| {
+            component[key]
+                .as_array()
+                .is_some_and(|parts| parts.iter().any(uses_byte_level))
+        })
+}
+
+fn decode_token(token: &str, byte_decoder: Option<&HashMap<char, u8>>) -> Vec<u8> {
+    if let Some(byte_decoder) = byte_decoder {
+        let mut bytes = Vec::with_capacity(token.len());
+        for c in token.chars() {
+            match byte_decoder.get(&c) {
+                Some(byte) => bytes.push(*byte),
+                None => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        }
+        return bytes;
+    }
+    // SentencePiece byte fallback tokens.
+    if let Some(hex) = token
+        .strip_prefix("<0x")
+        .and_then(|rest| rest.strip_suffix('>'))
+    {
+        if let Ok(byte) = u8::from_str_radix(hex, 16) {
+            return vec![byte];
+        }
+    }
+    token.replace('▁', " ").into_bytes()
+}
+
+// Inverse of GPT-2's `bytes_to_unicode`: printable bytes map to themselves, the rest to U+0100
+// onwards in byte order.
+fn byte_decoder() -> HashMap<char, u8> {
+    let printable = |byte: u8| matches!(byte, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF);
+    let mut decoder = HashMap::with_capacity(256);
+    let mut shifted = 0;
+    for byte in 0..=255u8 {
+        let c = if printable(byte) {
+            char::from(byte)
+        } else {
+            shifted += 1;
+            char::from_u32(255 + shifted).unwrap_or_default()
+        };
+        decoder.insert(c, byte);
+    }
+    decoder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn byte_decoder_inverts_bytes_to_unicode() {
+        let decoder = byte_decoder();
+        assert_eq!(decoder.len(), 256);
+        assert_eq!(decoder[&'A'], b'A');
+        assert_eq!(decoder[&'Ġ'], b' ');
+        assert_eq!(decoder[&'Ċ'], b'\n');
+        assert_eq!(decoder[&'\u{100}'], 0);
+        assert_eq!(decoder[&'\u{143}'], 0xAD);
+        let mut bytes: Vec<u8> = decoder.values().copied().collect();
+        bytes.sort_unstable();
+        bytes.dedup();
+        assert_eq!(bytes.len(), 256);
+    }
+
+    #[test]
+    fn decodes_byte_level_vocabularies() {
+        let tokenizer = json!({
+            "model": {"vocab": {"Hello": 0, "Ġworld": 1, "Ċ": 2, "Ã©": 3}},
+            "added_tokens": [
+                {"id": 4, "content": "<|im_end|>", "special": true},
+                {"id": 5, "content": "<tool_call>", "special": false},
+            ],
+            "decoder": {"type": "ByteLevel"},
+        });
+        let vocabulary = TokenVocabulary::from_tokenizer_json(&tokenizer.to_string()).unwrap();
+        assert_eq!(vocabulary.vocab_size(), 6);
+        assert_eq!(vocabulary.token_bytes(0), b"Hello");
+        assert_eq!(vocabulary.token_bytes(1), b" world");
+        assert_eq!(vocabulary.token_bytes(2), b"\n");
+        assert_eq!(vocabulary.token_bytes(3), "é".as_bytes());
+        assert_eq!(vocabulary.token_bytes(4), b"");
+        assert_eq!(vocabulary.token_bytes(5), b"<tool_call>");
+        assert_eq!(vocabulary.token_bytes(-1), b"");
+        assert_eq!(vocabulary.token_bytes(6), b"");
+        assert_eq!(vocabulary.eos_token_ids(), [4]);
+        assert!(vocabulary.is_eos(4));
+    }
+
+    #[test]
+    fn detects_byte_level_inside_sequences() {
+        let tokenizer = json!({
+            "model": {"vocab": {"Ġa": 0}},
+            "pre_tokenizer": {
+                "type": "Sequence",
+                "pretokenizers": [{"type": "Split"}, {"type": "ByteLevel"}],
+            },
+        });
+        let vocabulary = TokenVocabulary::from_tokenizer_json(&tokenizer.to_string()).unwrap();
+        assert_eq!(vocabulary.token_bytes(0), b" a");
+    }
+
+    #[test]
+    fn decodes_sentencepiece_vocabularies() {
+        let tokenizer = json!({
+            "model": {"vocab": [["<unk>", 0.0], ["▁Hello", -1.0], ["<0x0A>", 0.0]]},
+            "added_tokens": [{"id": 3, "content": "</s>", "special": true}],
+            "decoder": {"type": "Sequence", "decoders": [{"type": "Replace"}]},
+        });
+        let vocabulary = TokenVocabulary::from_tokenizer_json(&tokenizer.to_string())
+            .unwrap()
+            .with_eos_token_ids([3, 0]);
+        assert_eq!(vocabulary.token_bytes(1), b" Hello");
+        assert_eq!(vocabulary.token_bytes(2), b"\n");
+        assert_eq!(vocabulary.token_bytes(3), b"");
+        assert_eq!(vocabulary.eos_token_ids(), [3, 0]);
+    }
+
+    #[test]
+    fn token_index_walks_shared_prefixes_once() {
+        let vocabulary = TokenVocabulary::new(
+            vec![
+                b"a".to_vec(),
+                b"ab".to_vec(),
+                b"abc".to_vec(),
+                b"b".to_vec(),
+                Vec::new(),
+                b"a".to_vec(),
+                b"ba".to_vec(),
+            ],
+            vec![5],
+        );
+        let index = TokenIndex::new(&vocabulary);
+        let mut steps = 0;
+        let mut allowed = index.walk(0usize, |depth, byte| {
+            steps += 1;
+            (byte == b'a' || *depth > 0).then_some(depth + 1)
+        });
+        allowed.sort_unstable();
+        // The empty and EOS tokens are never offered, and `b` prunes `b` and `ba`.
+        assert_eq!(allowed, [0, 1, 2]);
+        assert_eq!(steps, 4);
+        assert_ne!(TokenIndex::new(&vocabulary).id(), index.id());
+    }
+
+    #[test]
+    fn rejects_tokenizers_without_a_vocabulary() {
+        assert!(TokenVocabulary::from_tokenizer_json("not json").is_err());
+        assert!(TokenVocabulary::from_tokenizer_json(r#"{"model": {}}"#).is_err());
+    }
+}